@@ -0,0 +1,124 @@
+use std::ops::{Index, IndexMut};
+
+pub const DEFAULT_WIDTH : usize = 10;
+pub const DEFAULT_HEIGHT : usize = 20;
+
+// cell value for garbage, so it can be drawn differently to pieces
+pub const GARBAGE : u8 = u8::MAX;
+
+// Rows above the visible part of the well. Pieces spawn up here and stacks
+// can poke into them, but they're never drawn.
+pub const BUFFER_ROWS : usize = 2;
+
+// The well's cells, indexed [y][x] with y = 0 at the top of the buffer zone.
+// 0 is empty, anything else is a palette colour.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Board {
+    width: usize,
+    hidden: usize,
+    rows: Vec<Vec<u8>>
+}
+
+impl Board {
+    // an empty well `height` rows tall on screen, plus the buffer zone
+    pub fn new(width: usize, height: usize) -> Board {
+        Board {
+            width,
+            hidden: BUFFER_ROWS,
+            rows: vec![vec![0; width]; height + BUFFER_ROWS]
+        }
+    }
+
+    // build a board from rows written out top to bottom, buffer zone included
+    pub fn from_rows<R: AsRef<[u8]>>(rows: &[R]) -> Board {
+        let rows : Vec<Vec<u8>> = rows.iter().map(|r| r.as_ref().to_vec()).collect();
+        Board {
+            width: rows.first().map_or(0, |r| r.len()),
+            hidden: BUFFER_ROWS,
+            rows
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    // every row, buffer zone included
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    // how many rows at the top are hidden
+    pub fn hidden(&self) -> usize {
+        self.hidden
+    }
+
+    pub fn visible_height(&self) -> usize {
+        self.rows.len() - self.hidden
+    }
+
+    pub fn rows(&self) -> std::slice::Iter<'_, Vec<u8>> {
+        self.rows.iter()
+    }
+
+    // (y, row) for the rows that are actually shown
+    pub fn visible_rows(&self) -> impl Iterator<Item = (usize, &Vec<u8>)> {
+        self.rows.iter().enumerate().skip(self.hidden)
+    }
+
+    // false if (x, y) is off the sides or the bottom, or already filled.
+    // Anywhere above the top of the board is open.
+    pub fn is_open(&self, x: i32, y: i32) -> bool {
+        if x < 0 || x >= self.width as i32 || y >= self.height() as i32 {
+            return false;
+        }
+        y < 0 || self.rows[y as usize][x as usize] == 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.iter().all(|row| row.iter().all(|&c| c == 0))
+    }
+
+    // take out row `y` and drop everything above it down by one
+    pub fn remove_row(&mut self, y: usize) {
+        self.rows.remove(y);
+        self.rows.insert(0, vec![0; self.width]);
+    }
+
+    // add a row at the bottom, lifting everything else up one. Returns the
+    // top row, which gets pushed off the board.
+    pub fn push_row(&mut self, row: Vec<u8>) -> Vec<u8> {
+        self.rows.push(row);
+        self.rows.remove(0)
+    }
+}
+
+impl Index<usize> for Board {
+    type Output = Vec<u8>;
+
+    fn index(&self, y: usize) -> &Vec<u8> {
+        &self.rows[y]
+    }
+}
+
+impl IndexMut<usize> for Board {
+    fn index_mut(&mut self, y: usize) -> &mut Vec<u8> {
+        &mut self.rows[y]
+    }
+}
+
+#[test]
+fn test_remove_row_drops_the_rest() {
+    let mut board = Board::new(4, 2);
+    board[2] = vec![1, 0, 0, 1];
+    board[3] = vec![1, 1, 1, 1];
+    assert_eq!(board.height(), 4);
+    assert_eq!(board.visible_height(), 2);
+
+    board.remove_row(3);
+    assert_eq!(board[3], vec![1, 0, 0, 1]);
+    assert_eq!(board[0], vec![0; 4]);
+    assert!(!board.is_open(-1, 0));
+    assert!(board.is_open(1, -5));
+    assert!(!board.is_open(0, 3));
+}
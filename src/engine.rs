@@ -0,0 +1,1478 @@
+use crate::rand::prelude::*;
+use crate::rand::rngs::StdRng;
+use crate::board::*;
+use crate::piece::*;
+use crate::randomizer::*;
+use crate::gravity::*;
+use crate::mode::*;
+use crate::scoring::*;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::Duration;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Spin {
+    None,
+    Mini,
+    Full
+}
+
+// Things that happened during play that the HUD might want to show off.
+// They pile up in `State::events` until someone calls `take_events`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameEvent {
+    // a piece locked and cleared some lines, or was a T-spin (maybe both)
+    Clear { lines: u32, spin: Spin, back_to_back: bool },
+    // those lines were the last thing left in the well
+    PerfectClear { lines: u32, back_to_back: bool },
+    // would have topped out, but in zen the well just gets emptied instead
+    BoardReset(TopOut)
+}
+
+// the guideline ways to lose
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TopOut {
+    BlockOut, // a new piece spawned on top of the stack
+    LockOut, // a piece locked without any of it showing in the well
+    GarbageOut // garbage pushed the stack up past the top of the buffer zone
+}
+
+impl TopOut {
+    pub fn name(self) -> &'static str {
+        match self {
+            TopOut::BlockOut => "Block Out",
+            TopOut::LockOut => "Lock Out",
+            TopOut::GarbageOut => "Garbage Out"
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum GameState {
+    Playing,
+    ClearingRows(u32), // ticks left of the animation
+    GameOver(TopOut),
+    Finished // reached the goal of the game mode
+}
+
+// Everything that's chosen once when a game starts
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub randomizer: RandomizerKind,
+    pub seed: Option<u64>, // pick one at random if not given
+    pub preview: usize, // how many upcoming pieces to show, 1 to MAX_PREVIEW
+    pub lock_delay: f32, // seconds a piece can sit on the stack before it locks
+    pub move_reset_limit: u32, // how many moves/rotations can restart the lock delay
+    pub das: f32, // seconds left/right has to be held before it starts repeating
+    pub arr: f32, // seconds between repeats once it does, 0 slides straight to the wall
+    pub gravity: GravityCurve,
+    pub scoring: ScoringKind,
+    pub well_width: usize,
+    pub well_height: usize, // visible rows, not counting the buffer zone
+    pub pieces: Rc<PieceSet>,
+    pub garbage_top_out: bool, // lose when garbage pushes blocks off the top, otherwise they're just lost
+    pub mode: GameMode,
+    pub sprint_lines: u16, // lines to clear to finish a sprint
+    pub ultra_time: f32, // seconds an ultra game lasts
+    pub dig_rows: usize, // rows of garbage to dig through
+    pub messiness: f32 // 0 lines the garbage holes up in one column, 1 puts every hole somewhere random
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            randomizer: RandomizerKind::Bag7,
+            seed: None,
+            preview: 5,
+            lock_delay: 0.5,
+            move_reset_limit: 15,
+            das: 0.167,
+            arr: 0.033,
+            gravity: GravityCurve::Linear,
+            scoring: ScoringKind::Guideline,
+            well_width: DEFAULT_WIDTH,
+            well_height: DEFAULT_HEIGHT,
+            pieces: PieceSet::standard(),
+            garbage_top_out: true,
+            mode: GameMode::Marathon,
+            sprint_lines: 40,
+            ultra_time: 120.0,
+            dig_rows: 10,
+            messiness: 0.3
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct State {
+    pub board: Board,
+    pub score: u32,
+    pub lines: u16,
+    pub level: u16,
+    pub current_piece_x: i32, // top-left of the current piece's bounding box
+    pub current_piece_y: i32,
+    pub current_piece: Piece,
+    pub next_pieces: VecDeque<Piece>, // the preview queue, next up at the front
+    pub held_piece: Option<Piece>,
+    pub hold_used: bool, // only one hold per piece
+    pub step_time: f32,
+    pub held: Held,
+    pub shift_dir: i32, // -1 for left, 1 for right, 0 when neither is held
+    pub das_timer: u32, // ticks the current direction has been held
+    pub auto_shifts: u32, // repeats made since it was pressed
+    pub status: GameState,
+    pub randomizer: Box<dyn Randomizer>,
+    pub seed: u64, // the same seed always deals the same pieces
+    pub rng: StdRng,
+    pub lock_timer: u32, // ticks the piece has been resting on the stack
+    pub lock_resets: u32,
+    pub tick_accumulator: Duration, // time not yet simulated
+    pub lowest_y: i32, // deepest row this piece has reached, falling further earns back the resets
+    pub last_rotation_kick: Option<usize>, // the kick used, if the last thing the piece did was rotate
+    pub fin_kick: bool, // that rotation was a quarter turn using the last (TST/fin) kick
+    pub events: Vec<GameEvent>,
+    pub combo: i32, // -1 until a piece clears something, then 0, 1, 2... for each one after that also does
+    pub back_to_back: bool, // the last clear was a tetris or T-spin
+    pub clearing: Option<Clear>, // the clear being animated, to check for a perfect clear afterwards
+    pub game_time: Duration, // time spent playing, in whole ticks
+    pub pieces_placed: u32,
+    pub splits: Vec<Duration>, // game time at every SPLIT_LINES lines
+    pub undo_stack: Vec<State>, // zen only: the game as it was before each lock
+    pub drop_points: u32, // scored by dropping the current piece, undo takes them back
+    pub options: Options
+}
+
+// keys that keep doing something for as long as they're held down
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HeldKey {
+    Left,
+    Right,
+    SoftDrop
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Held {
+    pub left: bool,
+    pub right: bool,
+    pub down: bool
+}
+
+// the top-left of the piece's bounding box when it spawns - centred (rounding
+// left), with the top row of most pieces just above the visible well.
+pub fn spawn_x(board: &Board, box_size: usize) -> i32 {
+    (board.width() as i32 - box_size as i32) / 2
+}
+
+pub fn spawn_y(board: &Board) -> i32 {
+    board.hidden() as i32 - 1
+}
+
+pub const MAX_PREVIEW : usize = 6;
+
+// how often a split time is taken
+pub const SPLIT_LINES : u16 = 10;
+
+// the engine always runs at a fixed 60 ticks a second, whatever the frame rate
+pub const TICK_HZ : u32 = 60;
+pub const TICK_SECONDS : f32 = 1.0 / TICK_HZ as f32;
+pub const TICK : Duration = Duration::from_nanos(1_000_000_000 / TICK_HZ as u64);
+// don't try and catch up on more than this after a stall
+const MAX_UPDATE : Duration = Duration::from_millis(250);
+
+// seconds spent flashing the cleared rows, and before a restart is allowed
+pub const CLEAR_DELAY : f32 = 0.6;
+pub const GAME_OVER_COOLDOWN : f32 = 1.5;
+
+pub fn seconds_to_ticks(seconds: f32) -> u32 {
+    (seconds * TICK_HZ as f32).round() as u32
+}
+
+pub fn piece_fits(state: &State, piece: &Piece, px: i32, py: i32) -> bool {
+    for (cx, cy, _) in state.options.pieces.blocks(piece) {
+        // off the sides, through the floor or already occupied. Above the top
+        // of the well is fine.
+        if !state.board.is_open(px + cx, py + cy) {
+            return false;
+        }
+    }
+
+    true
+}
+
+pub fn can_move_piece(state: &State, piece: &Piece, dx: i32, dy: i32) -> bool { // FIXME: state's a bit heavy of a thing to move around here
+    piece_fits(state, piece, state.current_piece_x + dx, state.current_piece_y + dy)
+}
+
+pub fn piece_will_land(state: &State) -> bool {
+    // looking for a situation where if the piece goes down one more, it will
+    // intersect a tile.
+    // if this returns true, just write the pieces to the storage where it already is.
+    !can_move_piece(state, &state.current_piece, 0, 1)
+}
+
+pub fn piece_will_lose(state: &State) -> bool {
+    // none of this piece has made it down into the visible part of the well
+    let hidden = state.board.hidden() as i32;
+    state.options.pieces.blocks(&state.current_piece).iter().all(|&(_, cy, _)| state.current_piece_y + cy < hidden)
+}
+
+pub fn top_out(state: &mut State, reason: TopOut) {
+    if state.options.mode == GameMode::Zen {
+        state.board = Board::new(state.board.width(), state.board.visible_height());
+        state.events.push(GameEvent::BoardReset(reason));
+        return;
+    }
+    state.status = GameState::GameOver(reason);
+    state.step_time = 0.0;
+}
+
+pub fn can_move_left(state: &State) -> bool { // FIXME: state's a bit heavy of a thing to move around here
+    can_move_piece(state, &state.current_piece, -1, 0)
+}
+
+pub fn can_move_right(state: &State) -> bool { // FIXME: state's a bit heavy of a thing to move around here
+    can_move_piece(state, &state.current_piece, 1, 0)
+}
+
+// a successful move or rotation while resting on the stack buys the player
+// some more time before it locks, but only so many times
+fn on_piece_moved(state: &mut State) {
+    if state.lock_timer > 0 && state.lock_resets < state.options.move_reset_limit {
+        state.lock_timer = 0;
+        state.lock_resets += 1;
+    }
+}
+
+pub fn move_left(state: &mut State) -> bool {
+    if can_move_left(state) {
+        state.current_piece_x -= 1;
+        on_piece_moved(state);
+        state.last_rotation_kick = None;
+        true
+    } else {
+        false
+    }
+}
+
+pub fn move_right(state: &mut State) -> bool {
+    if can_move_right(state) {
+        state.current_piece_x += 1;
+        on_piece_moved(state);
+        state.last_rotation_kick = None;
+        true
+    } else {
+        false
+    }
+}
+
+fn shift(state: &mut State, dir: i32) -> bool {
+    if dir < 0 { move_left(state) } else { move_right(state) }
+}
+
+// Pressing left or right moves straight away, then holding it down repeats
+// the move (see `update_auto_shift`). The most recently pressed direction wins.
+// During a line clear there's nothing to move yet, so it only starts charging.
+pub fn key_down(state: &mut State, key: HeldKey) {
+    let dir = match key {
+        HeldKey::Left => { state.held.left = true; -1 },
+        HeldKey::Right => { state.held.right = true; 1 },
+        HeldKey::SoftDrop => { state.held.down = true; return; }
+    };
+
+    state.shift_dir = dir;
+    state.das_timer = 0;
+    state.auto_shifts = 0;
+    if state.status == GameState::Playing {
+        shift(state, dir);
+    }
+}
+
+pub fn key_up(state: &mut State, key: HeldKey) {
+    match key {
+        HeldKey::Left => state.held.left = false,
+        HeldKey::Right => state.held.right = false,
+        HeldKey::SoftDrop => { state.held.down = false; return; }
+    }
+
+    // fall back to the other direction if it's still held
+    state.shift_dir = if state.held.left { -1 } else if state.held.right { 1 } else { 0 };
+    state.das_timer = 0;
+    state.auto_shifts = 0;
+}
+
+// Delayed auto shift: once a direction has been held for `das` seconds it
+// repeats every `arr` seconds, or slides all the way over when `arr` is 0.
+fn update_auto_shift(state: &mut State) {
+    if state.shift_dir == 0 {
+        return;
+    }
+
+    state.das_timer += 1;
+    let das = seconds_to_ticks(state.options.das);
+    if state.das_timer < das {
+        return;
+    }
+
+    let dir = state.shift_dir;
+    match (state.das_timer - das).checked_div(seconds_to_ticks(state.options.arr)) {
+        Some(repeats) => {
+            // repeats are due at das, das + arr, das + 2 * arr...
+            while state.auto_shifts < repeats + 1 {
+                shift(state, dir);
+                state.auto_shifts += 1;
+            }
+        },
+        None => while shift(state, dir) {} // 0 arr, straight to the wall
+    }
+}
+
+// DAS keeps charging through the clear animation, stopping one tick short so
+// the next piece shifts as soon as it's in play
+fn charge_auto_shift(state: &mut State) {
+    if state.shift_dir != 0 {
+        let das = seconds_to_ticks(state.options.das);
+        state.das_timer = (state.das_timer + 1).min(das.saturating_sub(1));
+    }
+}
+
+// Try to put the current piece into `rotated`, testing each SRS kick in turn.
+// Returns the offset of the first kick that fits.
+pub fn find_kick(state: &State, rotated: &Piece) -> Option<(i32, i32)> {
+    let from = state.current_piece.rotation;
+    find_kick_index(state, rotated).map(|i| state.options.pieces.kicks(rotated.kind, from, rotated.rotation)[i])
+}
+
+// which entry of the kick table `find_kick` would use
+pub fn find_kick_index(state: &State, rotated: &Piece) -> Option<usize> {
+    let from = state.current_piece.rotation;
+    state.options.pieces.kicks(rotated.kind, from, rotated.rotation).iter()
+        .position(|&(dx, dy)| can_move_piece(state, rotated, dx, dy))
+}
+
+pub fn can_rotate_cw(state: &State) -> bool {
+    find_kick(state, &state.current_piece.rotated_cw()).is_some()
+}
+
+pub fn can_rotate_ccw(state: &State) -> bool {
+    find_kick(state, &state.current_piece.rotated_ccw()).is_some()
+}
+
+pub fn can_rotate_180(state: &State) -> bool {
+    find_kick(state, &state.current_piece.rotated_180()).is_some()
+}
+
+fn rotate_to(state: &mut State, rotated: Piece) -> bool {
+    if let Some(i) = find_kick_index(state, &rotated) {
+        let from = state.current_piece.rotation;
+        let (dx, dy) = state.options.pieces.kicks(rotated.kind, from, rotated.rotation)[i];
+        // 180 tables are longer and their 5th kick is nothing special
+        state.fin_kick = i == 4 && rotated.rotation != from.flipped();
+        state.current_piece = rotated;
+        state.current_piece_x += dx;
+        state.current_piece_y += dy;
+        on_piece_moved(state);
+        state.last_rotation_kick = Some(i);
+        true
+    } else {
+        false
+    }
+}
+
+pub fn rotate_cw(state: &mut State) -> bool {
+    let rotated = state.current_piece.rotated_cw();
+    rotate_to(state, rotated)
+}
+
+pub fn rotate_ccw(state: &mut State) -> bool {
+    let rotated = state.current_piece.rotated_ccw();
+    rotate_to(state, rotated)
+}
+
+pub fn rotate_180(state: &mut State) -> bool {
+    let rotated = state.current_piece.rotated_180();
+    rotate_to(state, rotated)
+}
+
+#[test]
+fn test_rotate_kicks_off_the_left_wall() {
+    let mut state = State::new();
+    state.current_piece = Piece::new(PieceKind::T).rotated_cw();
+    state.current_piece_x = -1; // flat side against the wall
+    state.current_piece_y = 5;
+    assert!(can_move_piece(&state, &state.current_piece, 0, 0));
+
+    // pointing down needs the column left of the wall, so it has to kick right
+    rotate_cw(&mut state);
+    assert_eq!(state.current_piece.rotation, Rotation::Reverse);
+    assert_eq!((state.current_piece_x, state.current_piece_y), (0, 5));
+}
+
+#[test]
+fn test_rotate_ccw_and_180_kick_too() {
+    let mut state = State::new();
+    state.current_piece = Piece::new(PieceKind::I).rotated_ccw();
+    state.current_piece_x = -1; // standing up against the left wall
+    state.current_piece_y = 5;
+    assert!(can_move_piece(&state, &state.current_piece, 0, 0));
+
+    assert!(rotate_ccw(&mut state));
+    assert_eq!(state.current_piece.rotation, Rotation::Reverse);
+    assert_eq!(state.current_piece_x, 0);
+
+    // lying flat on the floor, flipping it over needs a kick back up
+    state.current_piece = Piece::new(PieceKind::T);
+    state.current_piece_x = 3;
+    state.current_piece_y = state.board.height() as i32 - 2;
+    assert!(rotate_180(&mut state));
+    assert_eq!(state.current_piece.rotation, Rotation::Reverse);
+    assert_eq!(state.current_piece_y, state.board.height() as i32 - 3);
+}
+
+pub fn land_piece(state: &mut State) {
+    for (cx, cy, cell) in state.options.pieces.blocks(&state.current_piece) {
+        let x = state.current_piece_x + cx;
+        let y = state.current_piece_y + cy;
+        if x < 0 || y < 0 { continue; } // bail out on this one if the cell is off screen
+
+        state.board[y as usize][x as usize] = cell;
+    }
+}
+
+pub fn random_piece(state: &mut State) -> Piece {
+    Piece::new(state.randomizer.next(&mut state.rng))
+}
+
+// take the piece at the front of the queue and top the queue back up
+pub fn pop_next_piece(state: &mut State) -> Piece {
+    let piece = random_piece(state);
+    state.next_pieces.push_back(piece);
+    state.next_pieces.pop_front().unwrap()
+}
+
+pub fn rows_complete(state: &State) -> u32 {
+    let mut count = 0;
+    for row in state.board.rows() {
+        if row.iter().all(|&c| c > 0) {
+            count += 1; // this row is filled
+        }
+    }
+    count
+}
+
+pub fn clear_completed_rows(state: &mut State) {
+    // start from the top, so the rows that drop down have already been checked
+    for row_idx in 0..state.board.height() {
+        if state.board[row_idx].iter().all(|&c| c > 0) {
+            state.board.remove_row(row_idx);
+        }
+    }
+}
+
+pub fn well_is_empty(state: &State) -> bool {
+    state.board.is_empty()
+}
+
+// Take the cleared rows away once the animation is done, and pay out
+// the perfect clear bonus if that emptied the whole well
+pub fn finish_clear(state: &mut State) {
+    clear_completed_rows(state);
+    check_block_out(state);
+
+    if let Some(clear) = state.clearing.take() {
+        if clear.lines > 0 && well_is_empty(state) {
+            state.score += state.options.scoring.rule().perfect_clear_points(&clear);
+            state.events.push(GameEvent::PerfectClear { lines: clear.lines, back_to_back: clear.back_to_back });
+        }
+    }
+}
+
+#[test]
+fn test_clear_completed_rows() {
+    let mut state = State {
+        board: Board::from_rows(&[
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 1
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 2
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 3
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 4
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 5
+            [ 0, 0, 0, 1, 1, 1, 1, 0, 0, 0 ], // 6
+            [ 1, 1, 1, 1, 1, 1, 1, 1, 1, 1 ], // 7
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 8
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 9
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 10
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 11
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 12
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 13
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 14
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 15
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 16
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 17
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 18
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 19
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 20
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 21
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 22
+        ]),
+        ..State::new()
+    };
+    clear_completed_rows(&mut state);
+    assert_eq!(state.board,
+        // ensure row 6 drops into row 7 which is obliterated
+        Board::from_rows(&[
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 1
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 2
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 3
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 4
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 5
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 6
+            [ 0, 0, 0, 1, 1, 1, 1, 0, 0, 0 ], // 7
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 8
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 9
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 10
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 11
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 12
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 13
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 14
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 15
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 16
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 17
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 18
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 19
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 20
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 21
+            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 22
+        ])
+    );
+}
+
+// is this square a wall, the floor or part of the stack?
+fn is_solid(state: &State, x: i32, y: i32) -> bool {
+    !state.board.is_open(x, y)
+}
+
+// The 3-corner rule: a T that got into place by rotating, with at least 3 of
+// the corners around its centre blocked, is a T-spin. It's only a mini if one
+// of the two corners it's pointing at is open, unless it took the last kick
+// of a quarter turn.
+pub fn detect_t_spin(state: &State) -> Spin {
+    if !state.options.pieces.def(state.current_piece.kind).t_spin || state.last_rotation_kick.is_none() {
+        return Spin::None;
+    }
+
+    let x = state.current_piece_x;
+    let y = state.current_piece_y;
+    // corners of the 3x3 box, in the order top left, top right, bottom right, bottom left
+    let corners = [
+        is_solid(state, x, y),
+        is_solid(state, x + 2, y),
+        is_solid(state, x + 2, y + 2),
+        is_solid(state, x, y + 2)
+    ];
+    if corners.iter().filter(|&&c| c).count() < 3 {
+        return Spin::None;
+    }
+
+    let (front_a, front_b) = match state.current_piece.rotation {
+        Rotation::Spawn => (0, 1),
+        Rotation::Right => (1, 2),
+        Rotation::Reverse => (2, 3),
+        Rotation::Left => (3, 0)
+    };
+    if (corners[front_a] && corners[front_b]) || state.fin_kick {
+        Spin::Full
+    } else {
+        Spin::Mini
+    }
+}
+
+pub fn on_piece_landed(state: &mut State) {
+    let spin = detect_t_spin(state);
+
+    // detect scoring (1, 2, 3, 4, etc)
+    let rows_completed = rows_complete(state);
+
+    // tetrises and T-spins in a row, with no easier clears in between
+    let difficult = rows_completed >= 4 || (rows_completed > 0 && spin != Spin::None);
+    let back_to_back = difficult && state.back_to_back;
+
+    if rows_completed > 0 {
+        state.back_to_back = difficult;
+        // every piece in a row that clears something adds to the combo
+        state.combo += 1;
+    } else {
+        state.combo = -1;
+    }
+
+    let clear = Clear {
+        lines: rows_completed,
+        spin,
+        back_to_back,
+        combo: state.combo,
+        level: state.level
+    };
+    state.score += state.options.scoring.rule().clear_points(&clear);
+
+    if rows_completed > 0 || spin != Spin::None {
+        state.events.push(GameEvent::Clear { lines: rows_completed, spin, back_to_back });
+    }
+
+    if rows_completed > 0 {
+        // switch to scoring animations if any scores were made
+        state.clearing = Some(clear);
+        state.status = GameState::ClearingRows(seconds_to_ticks(CLEAR_DELAY));
+
+        // level up every 10 lines
+        if (state.lines + rows_completed as u16) / 10 > (state.lines / 10) {
+            state.level += 1;
+        }
+
+        if (state.lines + rows_completed as u16) / SPLIT_LINES > (state.lines / SPLIT_LINES) {
+            state.splits.push(state.game_time);
+        }
+
+        state.lines += rows_completed as u16;
+    }
+    state.pieces_placed += 1;
+
+    // set up the next piece
+    //  - swap next piece into new piece, and compute the one after
+    let next = pop_next_piece(state);
+    spawn_piece(state, next);
+    //  - hold is allowed again
+    state.hold_used = false;
+    state.drop_points = 0;
+
+    check_goal(state);
+}
+
+// end the game if the mode's goal has been reached
+fn check_goal(state: &mut State) {
+    let done = match state.options.mode {
+        GameMode::Marathon | GameMode::Zen => false,
+        GameMode::Sprint => state.lines >= state.options.sprint_lines,
+        GameMode::Ultra => state.game_time >= ultra_duration(state),
+        GameMode::Dig => garbage_rows_left(state) == 0
+    };
+    if done {
+        // the last clear still counts (and gets cleared away), even though
+        // the animation won't get to play out
+        if matches!(state.status, GameState::ClearingRows(_)) {
+            state.status = GameState::Playing;
+            finish_clear(state);
+        }
+        state.status = GameState::Finished;
+        state.step_time = 0.0;
+    }
+}
+
+// how long an ultra game lasts, in whole ticks so the clock ends exactly on one
+fn ultra_duration(state: &State) -> Duration {
+    TICK * seconds_to_ticks(state.options.ultra_time)
+}
+
+// what's left on the clock in ultra
+pub fn time_left(state: &State) -> Duration {
+    ultra_duration(state).checked_sub(state.game_time).unwrap_or_default()
+}
+
+// pieces per second so far
+pub fn pieces_per_second(state: &State) -> f32 {
+    let seconds = state.game_time.as_secs_f32();
+    if seconds > 0.0 { state.pieces_placed as f32 / seconds } else { 0.0 }
+}
+
+// put a new piece at the top of the well
+pub fn spawn_piece(state: &mut State, piece: Piece) {
+    let def = state.options.pieces.def(piece.kind);
+    let (dx, dy) = def.spawn_offset;
+    state.current_piece_y = spawn_y(&state.board) + dy;
+    state.current_piece_x = spawn_x(&state.board, def.size) + dx;
+    state.current_piece = piece;
+    check_block_out(state);
+    state.lock_timer = 0;
+    state.lock_resets = 0;
+    state.lowest_y = state.current_piece_y;
+    state.last_rotation_kick = None;
+}
+
+// Push a row of garbage in under the stack, lifting everything up a row
+pub fn add_garbage_row(state: &mut State, row: Vec<u8>) {
+    let spilled = state.board.push_row(row);
+    if state.options.garbage_top_out && spilled.iter().any(|&c| c > 0) {
+        top_out(state, TopOut::GarbageOut);
+        return;
+    }
+
+    // the falling piece gets lifted too, if it's in the way
+    if !can_move_piece(state, &state.current_piece, 0, 0) {
+        state.current_piece_y -= 1;
+    }
+}
+
+// Fill the bottom of the well with `rows` rows of garbage, each with one hole.
+// It comes from the game's RNG, so the same seed digs through the same garbage.
+pub fn add_dig_garbage(state: &mut State, rows: usize) {
+    let width = state.board.width();
+    let mut hole = state.rng.gen_range(0, width);
+    for _ in 0..rows {
+        if state.rng.gen::<f32>() < state.options.messiness {
+            hole = state.rng.gen_range(0, width);
+        }
+        let mut row = vec![GARBAGE; width];
+        row[hole] = 0;
+        add_garbage_row(state, row);
+    }
+}
+
+// rows with garbage in them that aren't about to be cleared
+pub fn garbage_rows_left(state: &State) -> usize {
+    state.board.rows()
+        .filter(|row| row.contains(&GARBAGE) && !row.iter().all(|&c| c > 0))
+        .count()
+}
+
+// A new piece that spawns overlapping the stack ends the game. While rows are
+// being cleared this waits until they're gone (see `finish_clear`).
+fn check_block_out(state: &mut State) {
+    if state.status == GameState::Playing && !can_move_piece(state, &state.current_piece, 0, 0) {
+        top_out(state, TopOut::BlockOut);
+    }
+}
+
+// Stash the current piece in the hold slot, bringing back whatever was held
+// before (or the next piece if the slot was empty). Only once per piece.
+pub fn hold_piece(state: &mut State) -> bool {
+    if state.hold_used {
+        return false;
+    }
+
+    let stashed = Piece::new(state.current_piece.kind); // back to spawn rotation
+    let piece = match state.held_piece {
+        Some(held) => held,
+        None => pop_next_piece(state)
+    };
+    spawn_piece(state, piece);
+    state.held_piece = Some(stashed);
+    state.hold_used = true;
+    true
+}
+
+#[test]
+fn test_hold_once_per_piece() {
+    let mut state = State::new();
+    state.current_piece = Piece::new(PieceKind::T).rotated_cw();
+    state.next_pieces[0] = Piece::new(PieceKind::I);
+    state.current_piece_x = 0;
+
+    assert!(hold_piece(&mut state));
+    assert_eq!(state.held_piece, Some(Piece::new(PieceKind::T)));
+    assert_eq!(state.current_piece.kind, PieceKind::I);
+    assert_eq!(state.current_piece_x, spawn_x(&state.board, 4));
+
+    // locked out until the piece lands
+    assert!(!hold_piece(&mut state));
+    assert_eq!(state.current_piece.kind, PieceKind::I);
+
+    // after landing, holding again swaps with the stashed piece
+    hard_drop(&mut state);
+    let swapped = state.current_piece.kind;
+    assert!(hold_piece(&mut state));
+    assert_eq!(state.current_piece, Piece::new(PieceKind::T));
+    assert_eq!(state.held_piece, Some(Piece::new(swapped)));
+}
+
+// write the current piece into the well where it is and bring in the next one
+pub fn lock_piece(state: &mut State) {
+    if state.options.mode == GameMode::Zen {
+        save_undo(state);
+    }
+
+    if piece_will_lose(state) {
+        top_out(state, TopOut::LockOut);
+    } else {
+        // write the piece to the state
+        land_piece(state);
+        on_piece_landed(state);
+    }
+}
+
+// Remember the game as it is now, so `undo` can come back to it
+fn save_undo(state: &mut State) {
+    let stack = std::mem::take(&mut state.undo_stack);
+    let mut snapshot = state.clone();
+    snapshot.events.clear();
+    state.undo_stack = stack;
+    state.undo_stack.push(snapshot);
+}
+
+// Take back the last piece that locked, putting it back at the top of the well
+pub fn undo(state: &mut State) -> bool {
+    let mut snapshot = match state.undo_stack.pop() {
+        Some(snapshot) => snapshot,
+        None => return false
+    };
+    snapshot.undo_stack = std::mem::take(&mut state.undo_stack);
+    snapshot.held = state.held; // whatever keys are down now are still down
+    snapshot.shift_dir = state.shift_dir;
+    *state = snapshot;
+    state.score -= state.drop_points;
+    state.drop_points = 0;
+
+    let piece = Piece::new(state.current_piece.kind);
+    spawn_piece(state, piece);
+    true
+}
+
+// Swap the next piece in the queue for the one `steps` further along in the
+// piece set, wrapping around - for picking pieces by hand in zen
+pub fn cycle_next_piece(state: &mut State, steps: i32) {
+    let count = state.options.pieces.len() as i32;
+    if let Some(next) = state.next_pieces.front_mut() {
+        let kind = (next.kind.0 as i32 + steps).rem_euclid(count);
+        *next = Piece::new(PieceKind(kind as usize));
+    }
+}
+
+// Gravity - drop the piece one row if there's room. Once it's resting on the
+// stack it stays put until the lock delay runs out (see `update`).
+pub fn step_piece(state: &mut State) {
+    if !piece_will_land(state) {
+        // drop the piece
+        state.current_piece_y += 1;
+        state.last_rotation_kick = None;
+        if state.held.down {
+            let points = state.options.scoring.rule().soft_drop_points(1);
+            state.score += points;
+            state.drop_points += points;
+        }
+
+        if state.current_piece_y > state.lowest_y {
+            state.lowest_y = state.current_piece_y;
+            state.lock_resets = 0;
+        }
+    }
+}
+
+// how many rows the current piece can fall before it lands
+pub fn drop_distance(state: &State) -> i32 {
+    let mut dy = 0;
+    while can_move_piece(state, &state.current_piece, 0, dy + 1) {
+        dy += 1;
+    }
+    dy
+}
+
+// where the current piece would end up if it was hard dropped right now
+pub fn ghost_y(state: &State) -> i32 {
+    state.current_piece_y + drop_distance(state)
+}
+
+pub fn hard_drop(state: &mut State) {
+    let dy = drop_distance(state);
+    state.current_piece_y += dy;
+    let points = state.options.scoring.rule().hard_drop_points(dy as u32);
+    state.score += points;
+    state.drop_points += points;
+    if dy > 0 {
+        state.last_rotation_kick = None;
+    }
+    lock_piece(state);
+}
+
+// Advance the game by one fixed tick - gravity and lock delay while playing,
+// the row clearing animation, and the game over cooldown.
+pub fn tick(state: &mut State) {
+    if matches!(state.status, GameState::Playing | GameState::ClearingRows(_)) {
+        // the game clock only runs while playing, so it can't be cheated by
+        // stalling the frontend
+        state.game_time += TICK;
+        check_goal(state);
+        if state.status == GameState::Finished {
+            return;
+        }
+    }
+
+    match state.status {
+        GameState::Playing => {
+            update_auto_shift(state);
+
+            let mut gravity = state.options.gravity.cells_per_tick(state.level);
+
+            if state.held.down {
+                gravity *= 10.0; // drop faster when DOWN is held
+            }
+
+            if gravity >= TWENTY_G {
+                // straight to the bottom, however deep the well is
+                while !piece_will_land(state) {
+                    step_piece(state);
+                }
+                state.step_time = 0.0;
+            } else {
+                state.step_time += gravity;
+
+                while state.step_time >= 1.0 { // ehh, i don't like this while
+                    state.step_time -= 1.0;
+                    step_piece(state);
+                }
+            }
+
+            // lock once the piece has been resting on the stack long enough
+            if piece_will_land(state) {
+                state.lock_timer += 1;
+                if state.lock_timer >= seconds_to_ticks(state.options.lock_delay) {
+                    lock_piece(state);
+                }
+            } else {
+                state.lock_timer = 0;
+            }
+        },
+        GameState::ClearingRows(timer) => {
+            if timer <= 1 {
+                // clearing complete, return to game
+                state.status = GameState::Playing;
+
+                // delete the cleared rows!!!
+                finish_clear(state);
+            }
+            else {
+                // still clearing, step the timer down
+                state.status = GameState::ClearingRows(timer - 1);
+            }
+            charge_auto_shift(state);
+        },
+        GameState::GameOver(_) | GameState::Finished => {
+            state.step_time += TICK_SECONDS;
+        }
+    }
+}
+
+// Run however many ticks fit into the time that's passed since the last
+// update, so the game runs at the same speed whatever the frame rate is.
+pub fn update(state: &mut State, elapsed: Duration) {
+    state.tick_accumulator += elapsed.min(MAX_UPDATE);
+    while state.tick_accumulator >= TICK {
+        state.tick_accumulator -= TICK;
+        tick(state);
+    }
+}
+
+pub fn take_events(state: &mut State) -> Vec<GameEvent> {
+    std::mem::take(&mut state.events)
+}
+
+// Don't let a mashed key skip straight past the game over screen
+pub fn can_restart(state: &State) -> bool {
+    matches!(state.status, GameState::GameOver(_) | GameState::Finished) && state.step_time >= GAME_OVER_COOLDOWN
+}
+
+#[test]
+fn test_hard_drop_locks_on_the_floor() {
+    let mut state = State::new();
+    state.current_piece = Piece::new(PieceKind::O);
+    state.current_piece_x = 0;
+    state.current_piece_y = 0;
+    let floor = state.board.height();
+    assert_eq!(drop_distance(&state), floor as i32 - 2);
+
+    hard_drop(&mut state);
+    assert_eq!(state.score, (floor as u32 - 2) * 2);
+    assert_eq!(state.board[floor - 1][1..3], [4, 4]);
+    assert_eq!(state.board[floor - 2][1..3], [4, 4]);
+    let size = state.options.pieces.def(state.current_piece.kind).size;
+    assert_eq!((state.current_piece_x, state.current_piece_y), (spawn_x(&state.board, size), spawn_y(&state.board)));
+}
+
+impl State {
+    pub fn new() -> State {
+        State::with_options(&Options::default())
+    }
+
+    pub fn with_options(options: &Options) -> State {
+        let seed = options.seed.unwrap_or_else(|| rand::thread_rng().next_u64());
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut randomizer = options.randomizer.build(&options.pieces);
+        let current_piece = Piece::new(randomizer.next(&mut rng));
+        let preview = options.preview.clamp(1, MAX_PREVIEW);
+        let board = Board::new(options.well_width, options.well_height);
+        let next_pieces = (0..preview).map(|_| Piece::new(randomizer.next(&mut rng))).collect();
+
+        let mut state = State {
+            current_piece_x: 0,
+            current_piece_y: spawn_y(&board),
+            lowest_y: spawn_y(&board),
+            board,
+            score: 0,
+            lines: 0,
+            level: 0,
+            current_piece,
+            next_pieces,
+            held_piece: None,
+            hold_used: false,
+            step_time: 0.0,
+            held: Held::default(),
+            shift_dir: 0,
+            das_timer: 0,
+            auto_shifts: 0,
+            status: GameState::Playing,
+            randomizer,
+            seed,
+            rng,
+            lock_timer: 0,
+            tick_accumulator: Duration::from_secs(0),
+            lock_resets: 0,
+            last_rotation_kick: None,
+            fin_kick: false,
+            events: Vec::new(),
+            combo: -1,
+            back_to_back: false,
+            clearing: None,
+            game_time: Duration::from_secs(0),
+            pieces_placed: 0,
+            splits: Vec::new(),
+            undo_stack: Vec::new(),
+            drop_points: 0,
+            options: options.clone()
+        };
+        // pieces can nudge where they spawn
+        spawn_piece(&mut state, current_piece);
+        if options.mode == GameMode::Dig {
+            add_dig_garbage(&mut state, options.dig_rows);
+        }
+        state
+    }
+}
+
+#[test]
+fn test_same_seed_same_pieces() {
+    for kind in [RandomizerKind::Uniform, RandomizerKind::Bag7, RandomizerKind::Nes, RandomizerKind::Tgm].iter() {
+        let options = Options { randomizer: *kind, seed: Some(1234), ..Options::default() };
+        let mut a = State::with_options(&options);
+        let mut b = State::with_options(&options);
+        assert_eq!(a.seed, 1234);
+        for _ in 0..50 {
+            assert_eq!(random_piece(&mut a), random_piece(&mut b));
+        }
+    }
+}
+
+#[test]
+fn test_queue_keeps_its_length() {
+    let options = Options { preview: 3, ..Options::default() };
+    let mut state = State::with_options(&options);
+    let upcoming = state.next_pieces[0];
+    hard_drop(&mut state);
+    assert_eq!(state.current_piece, upcoming);
+    assert_eq!(state.next_pieces.len(), 3);
+}
+
+#[test]
+fn test_lock_delay_and_move_resets() {
+    let options = Options { lock_delay: 0.5, move_reset_limit: 2, ..Options::default() };
+    let mut state = State::with_options(&options);
+    spawn_piece(&mut state, Piece::new(PieceKind::T));
+    state.current_piece_x = 0;
+    state.current_piece_y += drop_distance(&state);
+    let landed = state.current_piece_y;
+
+    // resting on the stack, it takes the whole delay to lock
+    update(&mut state, Duration::from_millis(300));
+    assert_eq!(state.current_piece, Piece::new(PieceKind::T));
+
+    // moving restarts the delay, twice
+    for _ in 0..2 {
+        assert!(move_right(&mut state));
+        update(&mut state, Duration::from_millis(300));
+        assert_eq!(state.current_piece_y, landed);
+        assert_eq!(state.current_piece, Piece::new(PieceKind::T));
+    }
+
+    // out of resets, so it locks even though it just moved
+    assert!(move_right(&mut state));
+    update(&mut state, Duration::from_millis(300));
+    assert_eq!(state.current_piece_y, spawn_y(&state.board));
+}
+
+#[test]
+fn test_das_and_arr() {
+    let options = Options { das: 0.1, arr: 0.05, ..Options::default() };
+    let mut state = State::with_options(&options);
+    spawn_piece(&mut state, Piece::new(PieceKind::O)); // columns 4 and 5
+    state.step_time = -1000.0; // hold gravity off
+
+    key_down(&mut state, HeldKey::Left);
+    assert_eq!(state.current_piece_x, spawn_x(&state.board, 4) - 1);
+    update(&mut state, Duration::from_millis(90));
+    assert_eq!(state.current_piece_x, spawn_x(&state.board, 4) - 1);
+    update(&mut state, Duration::from_millis(20)); // das is charged
+    assert_eq!(state.current_piece_x, spawn_x(&state.board, 4) - 2);
+    update(&mut state, Duration::from_millis(100)); // two more repeats
+    assert_eq!(state.current_piece_x, spawn_x(&state.board, 4) - 4);
+
+    // pressing right takes over, and letting go of it goes back to left
+    key_down(&mut state, HeldKey::Right);
+    assert_eq!(state.current_piece_x, spawn_x(&state.board, 4) - 3);
+    key_up(&mut state, HeldKey::Right);
+    assert_eq!(state.shift_dir, -1);
+
+    key_up(&mut state, HeldKey::Left);
+    update(&mut state, Duration::from_millis(200));
+    assert_eq!(state.current_piece_x, spawn_x(&state.board, 4) - 3);
+}
+
+#[test]
+fn test_das_charges_during_a_clear() {
+    let options = Options { das: 0.1, arr: 0.05, ..Options::default() };
+    let mut state = State::with_options(&options);
+    let bottom = state.board.height() - 1;
+    for x in 4..state.board.width() {
+        state.board[bottom][x] = 1;
+    }
+    state.current_piece = Piece::new(PieceKind::I);
+    state.current_piece_x = 0;
+    hard_drop(&mut state);
+    assert!(matches!(state.status, GameState::ClearingRows(_)));
+
+    // held through the whole animation, so the next piece moves right away
+    key_down(&mut state, HeldKey::Right);
+    let x = state.current_piece_x;
+    while state.status != GameState::Playing {
+        tick(&mut state);
+    }
+    assert_eq!(state.current_piece_x, x);
+    state.step_time = -1000.0; // hold gravity off
+    tick(&mut state);
+    assert_eq!(state.current_piece_x, x + 1);
+}
+
+#[test]
+fn test_zero_arr_slides_to_the_wall() {
+    let options = Options { das: 0.1, arr: 0.0, ..Options::default() };
+    let mut state = State::with_options(&options);
+    spawn_piece(&mut state, Piece::new(PieceKind::O));
+    state.step_time = -1000.0;
+
+    key_down(&mut state, HeldKey::Right);
+    update(&mut state, Duration::from_millis(100));
+    assert_eq!(state.current_piece_x, state.board.width() as i32 - 3); // O fills the middle of its box
+}
+
+#[test]
+fn test_twenty_g_drops_straight_to_the_floor() {
+    let options = Options { gravity: GravityCurve::Guideline, ..Options::default() };
+    let mut state = State::with_options(&options);
+    state.level = 25;
+    spawn_piece(&mut state, Piece::new(PieceKind::O));
+    tick(&mut state);
+    assert_eq!(state.current_piece_y, state.board.height() as i32 - 2);
+}
+
+#[test]
+fn test_t_spin_double() {
+    let mut state = State::new();
+    let full = vec![1; state.board.width()];
+    let bottom = state.board.height() - 1;
+    state.board[bottom] = full.clone();
+    state.board[bottom][4] = 0;
+    state.board[bottom - 1] = full;
+    state.board[bottom - 1][3..6].copy_from_slice(&[0, 0, 0]);
+    state.board[bottom - 2][3] = 1; // the overhang
+
+    // pointing down into the slot, having just rotated in
+    state.current_piece = Piece::new(PieceKind::T).rotated_180();
+    state.current_piece_x = 3;
+    state.current_piece_y = bottom as i32 - 2;
+    state.last_rotation_kick = Some(0);
+    assert_eq!(detect_t_spin(&state), Spin::Full);
+
+    lock_piece(&mut state);
+    assert_eq!(take_events(&mut state), vec![GameEvent::Clear { lines: 2, spin: Spin::Full, back_to_back: false }]);
+    assert_eq!(state.score, 1200);
+    assert!(state.events.is_empty());
+}
+
+#[test]
+fn test_combo_and_back_to_back() {
+    let mut state = State::new();
+    let mut tetris_ready = vec![1; state.board.width()];
+    tetris_ready[0] = 0;
+    let floor = state.board.height();
+
+    // two tetrises in a row
+    for n in 0..2 {
+        for y in floor - 4..floor {
+            state.board[y] = tetris_ready.clone();
+        }
+        state.current_piece = Piece::new(PieceKind::I).rotated_cw();
+        state.current_piece_x = -2;
+        let score = state.score;
+        let drop = drop_distance(&state) as u32 * 2;
+        hard_drop(&mut state);
+        clear_completed_rows(&mut state);
+
+        if n == 0 {
+            assert_eq!(state.score - score, drop + 800);
+        } else {
+            // back to back, and a 1 combo
+            assert_eq!(state.score - score, drop + 800 * 3 / 2 + 50);
+            assert_eq!(take_events(&mut state).last(), Some(&GameEvent::Clear { lines: 4, spin: Spin::None, back_to_back: true }));
+        }
+        assert!(state.back_to_back);
+        assert_eq!(state.combo, n);
+    }
+
+    // a piece that clears nothing breaks the combo, but not the back to back
+    state.current_piece = Piece::new(PieceKind::O);
+    hard_drop(&mut state);
+    assert_eq!(state.combo, -1);
+    assert!(state.back_to_back);
+}
+
+#[test]
+fn test_perfect_clear() {
+    let mut state = State::new();
+    let bottom = state.board.height() - 1;
+    for x in 4..state.board.width() {
+        state.board[bottom][x] = 1;
+    }
+    state.current_piece = Piece::new(PieceKind::I);
+    state.current_piece_x = 0;
+    let drop = drop_distance(&state) as u32 * 2;
+    hard_drop(&mut state);
+    finish_clear(&mut state);
+
+    assert!(well_is_empty(&state));
+    assert_eq!(state.score, drop + 100 + 800);
+    assert_eq!(take_events(&mut state).last(), Some(&GameEvent::PerfectClear { lines: 1, back_to_back: false }));
+}
+
+#[test]
+fn test_perfect_clear_on_the_last_clear() {
+    let perfect_clear_setup = |state: &mut State| {
+        let bottom = state.board.height() - 1;
+        for x in 4..state.board.width() {
+            state.board[bottom][x] = 1;
+        }
+        state.current_piece = Piece::new(PieceKind::I);
+        state.current_piece_x = 0;
+        hard_drop(state);
+    };
+
+    // the line that reaches the sprint goal
+    let options = Options { mode: GameMode::Sprint, sprint_lines: 1, ..Options::default() };
+    let mut state = State::with_options(&options);
+    perfect_clear_setup(&mut state);
+    assert_eq!(state.status, GameState::Finished);
+    assert!(well_is_empty(&state));
+    assert_eq!(take_events(&mut state).last(), Some(&GameEvent::PerfectClear { lines: 1, back_to_back: false }));
+
+    // ultra time running out in the middle of the animation
+    let options = Options { mode: GameMode::Ultra, ultra_time: 1.0, ..Options::default() };
+    let mut state = State::with_options(&options);
+    for _ in 0..TICK_HZ - 2 {
+        tick(&mut state);
+    }
+    perfect_clear_setup(&mut state);
+    let score = state.score;
+    while state.status != GameState::Finished {
+        tick(&mut state);
+    }
+    assert!(well_is_empty(&state));
+    assert_eq!(state.score, score + 800);
+}
+
+#[test]
+fn test_sprint_finishes_on_the_line_goal() {
+    let options = Options { mode: GameMode::Sprint, sprint_lines: 12, ..Options::default() };
+    let mut state = State::with_options(&options);
+    state.lines = 8;
+    for _ in 0..TICK_HZ {
+        tick(&mut state); // a second of play
+    }
+
+    let floor = state.board.height();
+    for y in floor - 4..floor {
+        state.board[y] = vec![1; state.board.width()];
+        state.board[y][0] = 0;
+    }
+    state.current_piece = Piece::new(PieceKind::I).rotated_cw();
+    state.current_piece_x = -2;
+    hard_drop(&mut state);
+
+    assert_eq!(state.status, GameState::Finished);
+    assert_eq!(state.pieces_placed, 1);
+    assert_eq!(state.splits, vec![state.game_time]);
+    assert!((pieces_per_second(&state) - 1.0).abs() < 0.01);
+
+    // the clock stops
+    tick(&mut state);
+    assert_eq!(state.splits, vec![state.game_time]);
+}
+
+#[test]
+fn test_ultra_runs_out_of_time() {
+    let options = Options { mode: GameMode::Ultra, ultra_time: 2.0, ..Options::default() };
+    let mut state = State::with_options(&options);
+    for _ in 0..TICK_HZ {
+        tick(&mut state);
+    }
+    assert_eq!(state.status, GameState::Playing);
+    assert_eq!(time_left(&state), TICK * TICK_HZ);
+
+    // a long stall only counts for so much
+    update(&mut state, Duration::from_secs(10));
+    assert_eq!(state.status, GameState::Playing);
+
+    for _ in 0..TICK_HZ {
+        tick(&mut state);
+    }
+    assert_eq!(state.status, GameState::Finished);
+    assert_eq!(time_left(&state), Duration::from_secs(0));
+}
+
+#[test]
+fn test_dig_garbage() {
+    let options = Options { mode: GameMode::Dig, dig_rows: 8, messiness: 0.0, seed: Some(42), ..Options::default() };
+    let state = State::with_options(&options);
+    assert_eq!(garbage_rows_left(&state), 8);
+    assert_eq!(state.board, State::with_options(&options).board);
+
+    // tidy garbage has every hole in the same column
+    let bottom = state.board.height() - 1;
+    let hole = state.board[bottom].iter().position(|&c| c == 0).unwrap();
+    for y in bottom - 7..=bottom {
+        assert_eq!(state.board[y].iter().filter(|&&c| c == 0).count(), 1);
+        assert_eq!(state.board[y][hole], 0);
+    }
+
+    // once it's all gone the run is over
+    let mut state = State::with_options(&options);
+    state.board = Board::new(state.board.width(), state.board.visible_height());
+    hard_drop(&mut state);
+    assert_eq!(state.status, GameState::Finished);
+}
+
+#[test]
+fn test_zen_undo_and_no_top_out() {
+    let options = Options { mode: GameMode::Zen, ..Options::default() };
+    let mut state = State::with_options(&options);
+    assert!(!undo(&mut state));
+
+    // pick the next piece by hand
+    state.next_pieces[0] = Piece::new(PieceKind::S);
+    cycle_next_piece(&mut state, 1);
+    assert_eq!(state.next_pieces[0].kind, PieceKind::J);
+    cycle_next_piece(&mut state, -2);
+    assert_eq!(state.next_pieces[0].kind, PieceKind::Z);
+
+    let first = state.current_piece.kind;
+    hard_drop(&mut state);
+    let score = state.score;
+    hard_drop(&mut state);
+    assert_eq!(state.undo_stack.len(), 2);
+
+    // back to just before the second piece locked, and then the first
+    assert!(undo(&mut state));
+    assert_eq!(state.current_piece.kind, PieceKind::Z);
+    assert_eq!(state.score, score);
+    assert!(undo(&mut state));
+    assert_eq!(state.score, 0);
+    assert_eq!(state.current_piece.kind, first);
+    assert!(state.board.is_empty());
+    let size = state.options.pieces.def(first).size;
+    assert_eq!((state.current_piece_x, state.current_piece_y), (spawn_x(&state.board, size), spawn_y(&state.board)));
+
+    // topping out just empties the well
+    let spawn_row = spawn_y(&state.board) as usize + 1;
+    for y in spawn_row..state.board.height() {
+        state.board[y][4] = 1;
+    }
+    state.current_piece = Piece::new(PieceKind::O);
+    state.current_piece_x = 0;
+    hard_drop(&mut state);
+    assert_eq!(state.status, GameState::Playing);
+    assert!(state.board.is_empty());
+    assert_eq!(take_events(&mut state).last(), Some(&GameEvent::BoardReset(TopOut::BlockOut)));
+}
+
+#[test]
+fn test_top_outs() {
+    // a piece that locks up in the buffer zone
+    let mut state = State::new();
+    let height = state.board.height();
+    for y in state.board.hidden()..height {
+        state.board[y][0] = 1;
+    }
+    state.current_piece = Piece::new(PieceKind::O);
+    state.current_piece_x = -1;
+    state.current_piece_y = 0;
+    lock_piece(&mut state);
+    assert_eq!(state.status, GameState::GameOver(TopOut::LockOut));
+
+    // the next piece has nowhere to go
+    let mut state = State::new();
+    let spawn_row = spawn_y(&state.board) as usize + 1;
+    for y in spawn_row..height {
+        state.board[y][4] = 1;
+    }
+    state.current_piece = Piece::new(PieceKind::O);
+    state.current_piece_x = 0;
+    hard_drop(&mut state);
+    assert_eq!(state.status, GameState::GameOver(TopOut::BlockOut));
+
+    // garbage pushing the stack off the top, unless that's turned off
+    for &garbage_top_out in [true, false].iter() {
+        let options = Options { garbage_top_out, ..Options::default() };
+        let mut state = State::with_options(&options);
+        state.board[0][0] = 1;
+        let garbage = vec![1; state.board.width()];
+        add_garbage_row(&mut state, garbage);
+        assert_eq!(state.status == GameState::GameOver(TopOut::GarbageOut), garbage_top_out);
+    }
+}
+
+#[test]
+fn test_pentominoes_land_and_clear() {
+    let options = Options { pieces: PieceSet::pentris(), ..Options::default() };
+    let mut state = State::with_options(&options);
+    let i = PieceKind(state.options.pieces.pieces.iter().position(|p| p.name == "I").unwrap());
+    let bottom = state.board.height() - 1;
+    for x in 5..state.board.width() {
+        state.board[bottom][x] = 1;
+    }
+
+    // the long I spans five columns, so it fills the rest of the row
+    spawn_piece(&mut state, Piece::new(i));
+    assert_eq!(state.current_piece_x, 2);
+    state.current_piece_x = 0;
+    hard_drop(&mut state);
+    assert_eq!(rows_complete(&state), 1);
+    finish_clear(&mut state);
+    assert!(well_is_empty(&state));
+}
+
+#[test]
+fn test_t_spin_needs_a_rotation() {
+    let mut state = State::new();
+    state.current_piece = Piece::new(PieceKind::T);
+    state.current_piece_x = 0;
+    let floor = state.board.height();
+    state.current_piece_y = floor as i32 - 2; // both bottom corners are the floor
+    state.board[floor - 2][0] = 1;
+    assert_eq!(detect_t_spin(&state), Spin::None);
+
+    state.last_rotation_kick = Some(0);
+    assert_eq!(detect_t_spin(&state), Spin::Mini);
+
+    // the last kick only makes it a full spin on a quarter turn
+    state.last_rotation_kick = Some(4);
+    assert_eq!(detect_t_spin(&state), Spin::Mini);
+    state.fin_kick = true;
+    assert_eq!(detect_t_spin(&state), Spin::Full);
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::new()
+    }
+}
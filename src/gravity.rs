@@ -0,0 +1,71 @@
+use std::str::FromStr;
+
+// Gravity is measured in cells per tick ("G"). Anything under 1 takes several
+// ticks per row, and 20G drops a piece to the bottom of the well instantly.
+pub const TWENTY_G : f32 = 20.0;
+
+// NES frames per cell for levels 0 to 29, after that it's always 1
+const NES_FRAMES_PER_CELL : [u32; 30] = [
+    48, 43, 38, 33, 28, 23, 18, 13, 8, 6,
+    5, 5, 5, 4, 4, 4, 3, 3, 3, 2,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 1
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GravityCurve {
+    Linear, // the original: gets a bit faster every level, forever
+    Guideline,
+    Nes
+}
+
+impl GravityCurve {
+    // how many cells the piece falls per tick (at 60 ticks a second) on `level`
+    pub fn cells_per_tick(self, level: u16) -> f32 {
+        match self {
+            GravityCurve::Linear => {
+                let rows_per_second = 1.5 + ((level + 1) as f32 * 0.9);
+                rows_per_second / 60.0
+            },
+            GravityCurve::Guideline => {
+                // levels start at 1 in the guideline, and 0 here
+                let level = (level + 1) as f32;
+                let seconds_per_row = (0.8 - ((level - 1.0) * 0.007)).max(0.0).powf(level - 1.0);
+                if seconds_per_row <= 0.0 {
+                    TWENTY_G
+                } else {
+                    (1.0 / (seconds_per_row * 60.0)).min(TWENTY_G)
+                }
+            },
+            GravityCurve::Nes => {
+                let frames = NES_FRAMES_PER_CELL.get(level as usize).cloned().unwrap_or(1);
+                1.0 / frames as f32
+            }
+        }
+    }
+}
+
+impl FromStr for GravityCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(GravityCurve::Linear),
+            "guideline" => Ok(GravityCurve::Guideline),
+            "nes" => Ok(GravityCurve::Nes),
+            _ => Err(format!("unknown gravity '{}' (try linear, guideline or nes)", s))
+        }
+    }
+}
+
+#[test]
+fn test_gravity_curves() {
+    // guideline level 1 is one row a second, and it's 20G by level 20
+    assert!((GravityCurve::Guideline.cells_per_tick(0) - 1.0 / 60.0).abs() < 1e-6);
+    assert!(GravityCurve::Guideline.cells_per_tick(5) > GravityCurve::Guideline.cells_per_tick(4));
+    assert_eq!(GravityCurve::Guideline.cells_per_tick(19), TWENTY_G);
+    assert_eq!(GravityCurve::Guideline.cells_per_tick(500), TWENTY_G);
+
+    assert_eq!(GravityCurve::Nes.cells_per_tick(0), 1.0 / 48.0);
+    assert_eq!(GravityCurve::Nes.cells_per_tick(29), 1.0);
+    assert_eq!(GravityCurve::Nes.cells_per_tick(100), 1.0);
+}
@@ -0,0 +1,9 @@
+extern crate rand;
+
+pub mod board;
+pub mod engine;
+pub mod gravity;
+pub mod mode;
+pub mod piece;
+pub mod randomizer;
+pub mod scoring;
@@ -1,5 +1,6 @@
 extern crate sdl2;
 extern crate rand;
+extern crate tetris;
 
 use crate::sdl2::event::Event;
 use crate::sdl2::keyboard::Keycode;
@@ -9,40 +10,25 @@ use crate::sdl2::rect::Rect;
 use crate::sdl2::render::WindowCanvas;
 use crate::sdl2::image::{LoadTexture, InitFlag};
 use crate::rand::prelude::*;
+use tetris::board::{Board, GARBAGE};
+use tetris::engine::*;
+use tetris::mode::GameMode;
+use tetris::piece::PieceSet;
 use std::fs;
 use std::time::{Instant, Duration};
 
-const WELL_HEIGHT : usize = 22;
-const WELL_WIDTH : usize = 10;
 const FRAMERATE_HZ : u32 = 30;
 
+// biggest well that still gets 10px tiles in the 800x600 window
+const MAX_WELL_WIDTH : usize = 40;
+const MAX_WELL_HEIGHT : usize = 60;
+
 macro_rules! rgb {
     ($r:expr, $g:expr, $b:expr) => {
         Color::RGB($r, $g, $b)
     }
 }
 
-#[derive(PartialEq)]
-enum GameState {
-    Playing,
-    ClearingRows(f32),
-    GameOver
-}
-
-struct State {
-    cells: [[u8; WELL_WIDTH]; WELL_HEIGHT],
-    score: u32,
-    lines: u16,
-    level: u16,
-    current_piece_x: u32,
-    current_piece_y: u32,
-    current_piece: [[u8; 4]; 4], // 4x4 should be enough room for the current piece.
-    next_piece: [[u8; 4]; 4],
-    step_time: f32,
-    dropping: bool, // FIXME: this needs a better idea...
-    status: GameState
-}
-
 fn get_backgrounds() -> Vec<std::path::PathBuf> {
     let paths = fs::read_dir("./backgrounds").unwrap();
     let mut v = Vec::<std::path::PathBuf>::new();
@@ -52,119 +38,6 @@ fn get_backgrounds() -> Vec<std::path::PathBuf> {
     v
 }
 
-fn is_pivot_cell(cell: u8) -> bool {
-    cell & 0x80 != 0
-}
-
-fn find_pivot_offset(piece: &[[u8; 4]; 4]) -> (u32, u32) {
-    for (cy, row) in piece.iter().enumerate() {
-        for (cx, cell) in row.iter().enumerate() {
-            if *cell > 0 && is_pivot_cell(*cell) {
-                return (cx as u32, cy as u32);
-            }
-        }
-    }
-    (0, 0) // FIXME: should crash...
-}
-
-fn piece_will_land(state: &State) -> bool {
-    // looking for a situation where if the piece goes down one more, it will
-    // intersect a tile.
-    // if this returns true, just write the pieces to the storage where it already is.
-    let (pivot_x, pivot_y) = find_pivot_offset(&state.current_piece);
-
-    for (cy, row) in state.current_piece.iter().enumerate() {
-        for (cx, cell) in row.iter().enumerate() {
-            if *cell > 0 {
-                let x : i32 = state.current_piece_x as i32 - pivot_x as i32 + cx as i32;
-                if x < 0 { continue; }
-                // Test for one deeper
-                let y : i32 = (state.current_piece_y + 1) as i32 - pivot_y as i32 + cy as i32;
-                if y < 0 { continue; } // bail out on this one if the cell is off screen
-
-                if y >= (WELL_HEIGHT as i32) {
-                    return true; // landed on bottom of screen
-                }
-
-                if x < WELL_WIDTH as i32 {
-                    // check this cell
-                    if state.cells[y as usize][x as usize] > 0 {
-                        return true; // there's already some landed garbage here
-                    }
-                }
-            }
-        }
-    }
-
-    false
-}
-
-fn piece_will_lose(state: &State) -> bool {
-    let (pivot_x, pivot_y) = find_pivot_offset(&state.current_piece);
-
-    for (cy, row) in state.current_piece.iter().enumerate() {
-        for (cx, cell) in row.iter().enumerate() {
-            if *cell > 0 {
-                let x : i32 = state.current_piece_x as i32 - pivot_x as i32 + cx as i32;
-                if x < 0 { continue; }
-                // Test for one deeper
-                let y : i32 = state.current_piece_y as i32 - pivot_y as i32 + cy as i32;
-                if y < 0 {
-                    //println!("Loss detected, y = {}", y);
-                    return true;
-                }
-                // at least part of this piece has landed off the top, they lose
-            }
-        }
-    }
-
-    false
-}
-
-fn can_move_piece(state: &State, piece: &[[u8; 4]; 4], dx: i32, dy: i32) -> bool { // FIXME: state's a bit heavy of a thing to move around here
-    // FIXME: de-duplicate...
-    // ...also clean up
-    let (pivot_x, pivot_y) = find_pivot_offset(piece);
-
-    for cy in 0..4 {
-        for cx in 0..4 {
-            let cell = piece[cy][cx];
-            if cell > 0 {
-                let x : i32 = (state.current_piece_x as i32 + dx) - pivot_x as i32 + cx as i32;
-                if x < 0 { return false; }
-                // Test for one deeper
-                let y : i32 = (state.current_piece_y as i32 + dy) - pivot_y as i32 + cy as i32;
-                if y < 0 { continue; } // bail out on this one if the cell is off screen
-
-                if y >= (WELL_HEIGHT as i32) {
-                    return false; // landed on bottom of screen
-                }
-
-                if x < WELL_WIDTH as i32 {
-                    // check this cell
-                    if state.cells[y as usize][x as usize] > 0 {
-                        return false; // cell is occupied already
-                    }
-                }
-
-                if x >= WELL_WIDTH as i32 {
-                    return false; // can't move this cell outside of the map right side
-                }
-            }
-        }
-    }
-
-    true
-}
-
-fn can_move_left(state: &State) -> bool { // FIXME: state's a bit heavy of a thing to move around here
-    can_move_piece(&state, &state.current_piece, -1, 0)
-}
-
-fn can_move_right(state: &State) -> bool { // FIXME: state's a bit heavy of a thing to move around here
-    can_move_piece(&state, &state.current_piece, 1, 0)
-}
-
 fn clip(x: u32, src_width: u32, clip_width: u32) -> u32 {
     if (x + 1) * src_width > clip_width {
         // clip back to whatever i can keep
@@ -175,18 +48,38 @@ fn clip(x: u32, src_width: u32, clip_width: u32) -> u32 {
     }
 }
 
-fn draw_well<T : sdl2::render::RenderTarget>(width: u32, height: u32, background_idx: u16, backgrounds: &Vec<sdl2::render::Texture>, canvas: &mut Canvas<T>) -> (u32, u32) {
-    let tile_size = height / (WELL_HEIGHT as u32);
-    let well_x = (width - (WELL_WIDTH as u32 * tile_size)) / 2;
-    let well_y = (height - (WELL_HEIGHT as u32 * tile_size)) / 2;
+// biggest a tile gets, the size they are in the default 10x20 well
+const MAX_TILE_SIZE : u32 = 30;
+
+// as big as the tiles can be with the visible rows filling the screen, and
+// the well taking up no more than half its width (wide boards get smaller
+// tiles). Small wells stop growing at MAX_TILE_SIZE so the hud still fits.
+fn tile_size(board: &Board, width: u32, height: u32) -> u32 {
+    (height / board.visible_height() as u32).min(width / 2 / board.width() as u32).min(MAX_TILE_SIZE)
+}
+
+// where the well sits on screen, centred and as tall as will fit
+fn well_rect(board: &Board, width: u32, height: u32) -> Rect {
+    let tile_size = tile_size(board, width, height);
+    let well_width_px = board.width() as u32 * tile_size;
+    let well_height_px = board.visible_height() as u32 * tile_size;
+    let well_x = (width - well_width_px) / 2;
+    let well_y = (height - well_height_px) / 2;
+    Rect::new(well_x as i32, well_y as i32, well_width_px, well_height_px)
+}
+
+fn draw_well<T : sdl2::render::RenderTarget>(board: &Board, width: u32, height: u32, background_idx: u16, backgrounds: &[sdl2::render::Texture], canvas: &mut Canvas<T>) -> (u32, u32) {
+    let well = well_rect(board, width, height);
+    let well_x = well.x() as u32;
+    let well_y = well.y() as u32;
 
-    let well_width_px = WELL_WIDTH as u32 * tile_size;
-    let well_height_px = WELL_HEIGHT as u32 * tile_size;
+    let well_width_px = well.width();
+    let well_height_px = well.height();
 
     // Now centre it and draw the well
     // add a cool border.
     canvas.set_draw_color(rgb!(200, 200, 200));
-    canvas.fill_rect(Rect::new(well_x as i32 - 2, well_y as i32 - 2, well_width_px + 4, well_height_px as u32 + 4)).unwrap();
+    canvas.fill_rect(Rect::new(well_x as i32 - 2, well_y as i32 - 2, well_width_px + 4, well_height_px + 4)).unwrap();
 
     // draw the inner well (only visible if background draw fails)
     canvas.set_draw_color(rgb!(255, 0, 255));
@@ -202,7 +95,7 @@ fn draw_well<T : sdl2::render::RenderTarget>(width: u32, height: u32, background
             let clip_height = clip(y, q.height, well_height_px);
             let src = Rect::new(0, 0, clip_width, clip_height);
             let dest = Rect::new((well_x + x * q.width) as i32, (well_y + y * q.height) as i32, clip_width, clip_height);
-            canvas.copy(&background, src, dest).unwrap();
+            canvas.copy(background, src, dest).unwrap();
         }
     }
 
@@ -212,12 +105,42 @@ fn draw_well<T : sdl2::render::RenderTarget>(width: u32, height: u32, background
     (well_x, well_y)
 }
 
-fn render_cells<T : sdl2::render::RenderTarget>(state: &State, width: u32, height: u32, backgrounds: &Vec<sdl2::render::Texture>, canvas: &mut Canvas<T>) {
+// garbage is grey so it stands out from everything the player put there
+const GARBAGE_COLOUR : Color = rgb!(110, 110, 110);
+
+// preview boxes are as wide as the biggest piece (at least 4 tiles), and one
+// row shorter so there's room for the piece lying flat and a gap
+fn preview_box(set: &PieceSet) -> (u32, u32) {
+    let size = set.max_size().max(4) as u32;
+    (size, size - 1)
+}
+
+// draw a piece centred in its own preview box, rather than at its raw offset
+// within its bounding box
+fn draw_piece_in_box<T : sdl2::render::RenderTarget>(blocks: Vec<(i32, i32, u8)>, area: Rect, tile_size: u32, colour: Option<Color>, palette: &[Color], canvas: &mut Canvas<T>) {
+    let min_x = blocks.iter().map(|b| b.0).min().unwrap_or(0);
+    let max_x = blocks.iter().map(|b| b.0).max().unwrap_or(0);
+    let min_y = blocks.iter().map(|b| b.1).min().unwrap_or(0);
+    let max_y = blocks.iter().map(|b| b.1).max().unwrap_or(0);
+
+    let tile = tile_size as i32;
+    let left = area.x() + (area.width() as i32 - (max_x - min_x + 1) * tile) / 2;
+    let top = area.y() + (area.height() as i32 - tile - (max_y - min_y + 1) * tile) / 2;
+
+    for (cx, cy, cell) in blocks {
+        canvas.set_draw_color(colour.unwrap_or(palette[(cell as usize) % palette.len()]));
+        canvas.fill_rect(
+            Rect::new(left + (cx - min_x) * tile, top + (cy - min_y) * tile, tile_size, tile_size)
+        ).unwrap();
+    }
+}
+
+fn render_cells<T : sdl2::render::RenderTarget>(state: &State, show_ghost: bool, width: u32, height: u32, backgrounds: &[sdl2::render::Texture], canvas: &mut Canvas<T>) {
     assert!(width > 0);
     assert!(height > 0);
 
     // FIXME: Don't alloc this every time, make it global
-    let palette = vec!
+    let palette =
         [ rgb!(240, 232, 205)
         , rgb!(252, 169, 133)
         // yellows
@@ -233,19 +156,21 @@ fn render_cells<T : sdl2::render::RenderTarget>(state: &State, width: u32, heigh
         , rgb!(249, 140, 182)
         ];
 
-    let tile_size = height / (WELL_HEIGHT as u32);
+    let tile_size = tile_size(&state.board, width, height);
+    let hidden = state.board.hidden();
 
-    let (well_x, well_y) = draw_well(width, height, state.level, backgrounds, canvas);
+    let (well_x, well_y) = draw_well(&state.board, width, height, state.level, backgrounds, canvas);
 
     // FIXME: Remove all this ugly duplicated code...
     // all we're doing is shifting the palette!!!
 
     match state.status {
         GameState::Playing => {
-            for (y, row) in state.cells.iter().enumerate() {
+            for (y, row) in state.board.visible_rows() {
+                let y = y - hidden;
                 for (x, cell) in row.iter().enumerate() {
                     if *cell > 0 {
-                        let cell_colour = palette[(*cell as usize) % palette.len()];
+                        let cell_colour = if *cell == GARBAGE { GARBAGE_COLOUR } else { palette[(*cell as usize) % palette.len()] };
                         canvas.set_draw_color(cell_colour);
                         canvas.fill_rect(
                             Rect::new(well_x as i32 + (x as u32 * tile_size) as i32, well_y as i32 + (y as u32 * tile_size) as i32, tile_size, tile_size)
@@ -255,10 +180,11 @@ fn render_cells<T : sdl2::render::RenderTarget>(state: &State, width: u32, heigh
             }
         },
         GameState::ClearingRows(_t) => {
-            for (y, row) in state.cells.iter().enumerate() {
+            for (y, row) in state.board.visible_rows() {
+                let y = y - hidden;
                 for (x, cell) in row.iter().enumerate() {
                     if *cell > 0 {
-                        let mut cell_colour = palette[(*cell as usize) % palette.len()];
+                        let mut cell_colour = if *cell == GARBAGE { GARBAGE_COLOUR } else { palette[(*cell as usize) % palette.len()] };
                         if row.iter().all(|&c| c > 0) {
                             // this is a clearing row, it should twinkle...
                             // TODO: a better, time based twinkle
@@ -276,82 +202,64 @@ fn render_cells<T : sdl2::render::RenderTarget>(state: &State, width: u32, heigh
         _ => {} // don't do anything special here
     }
 
-    // draw the actively moving sprite
-    let (pivot_x, pivot_y) = find_pivot_offset(&state.current_piece);
-
-    for (cy, row) in state.current_piece.iter().enumerate() {
-        for (cx, cell) in row.iter().enumerate() {
-            if *cell > 0 {
-                let x : i32 = state.current_piece_x as i32 - pivot_x as i32 + cx as i32;
-                if x < 0 { continue; }
-                let y : i32 = state.current_piece_y as i32 - pivot_y as i32 + cy as i32;
-                if y < 0 { continue; } // bail out on this one if the cell is off screen
-
-                let x = ((x as u32) * tile_size) + well_x;
-                let y = ((y as u32) * tile_size) + well_y;
-                let cell_colour = palette[((*cell & 0x7f) as usize) % palette.len()];
-                canvas.set_draw_color(cell_colour);
-                canvas.fill_rect(
-                    Rect::new(x as i32, y as i32, tile_size, tile_size)
-                ).unwrap();
-
-                // TODO: remove this duplicate code somehow, it'd be nice...
-            }
+    // draw the ghost outline where the piece will land
+    if show_ghost && state.status == GameState::Playing {
+        let ghost_y = ghost_y(state);
+        for (cx, cy, cell) in state.options.pieces.blocks(&state.current_piece) {
+            let x = state.current_piece_x + cx;
+            let y = ghost_y + cy;
+            if x < 0 || y < hidden as i32 { continue; } // bail out on this one if the cell is off screen
+            let y = y - hidden as i32;
+
+            let x = ((x as u32) * tile_size) + well_x;
+            let y = ((y as u32) * tile_size) + well_y;
+            let cell_colour = palette[(cell as usize) % palette.len()];
+            canvas.set_draw_color(cell_colour);
+            canvas.draw_rect(Rect::new(x as i32, y as i32, tile_size, tile_size)).unwrap();
+            canvas.draw_rect(Rect::new(x as i32 + 1, y as i32 + 1, tile_size - 2, tile_size - 2)).unwrap();
         }
     }
 
-    // draw the 'next' piece (HACK)
-    let next_x = 10;
-    let next_y = 110;
-    // FIXME: re-pivot the 'next' piece so it looks tucked
-    for (cy, row) in state.next_piece.iter().enumerate() {
-        for (cx, cell) in row.iter().enumerate() {
-            if *cell > 0 {
-                let x = ((cx as u32) * tile_size) + next_x;
-                let y = ((cy as u32) * tile_size) + next_y;
-                let cell_colour = palette[((*cell & 0x7f) as usize) % palette.len()];
-                canvas.set_draw_color(cell_colour);
-                canvas.fill_rect(
-                    Rect::new(x as i32, y as i32, tile_size, tile_size)
-                ).unwrap();
-
-                // TODO: remove this duplicate code somehow, it'd be nice...
-            }
-        }
+    // draw the actively moving sprite
+    for (cx, cy, cell) in state.options.pieces.blocks(&state.current_piece) {
+        let x = state.current_piece_x + cx;
+        let y = state.current_piece_y + cy;
+        if x < 0 || y < hidden as i32 { continue; } // bail out on this one if the cell is off screen
+        let y = y - hidden as i32;
+
+        let x = ((x as u32) * tile_size) + well_x;
+        let y = ((y as u32) * tile_size) + well_y;
+        let cell_colour = palette[(cell as usize) % palette.len()];
+        canvas.set_draw_color(cell_colour);
+        canvas.fill_rect(
+            Rect::new(x as i32, y as i32, tile_size, tile_size)
+        ).unwrap();
     }
 
-    // draw the pivot point for debugging (DEBUG)
-    let x = (state.current_piece_x * tile_size) + well_x;
-    let y = (state.current_piece_y * tile_size) + well_y;
-    canvas.set_draw_color(rgb!(255,255,255));
-    canvas.fill_rect(
-        Rect::new(x as i32 + (tile_size as i32 / 2 - 2), y as i32 + (tile_size as i32 / 2 - 2), 4, 4)
-    ).unwrap();
-
-    // done drawing, reset colour state
-    canvas.set_draw_color(rgb!(0, 0, 0));
-}
-
-// rotate 90 degrees clockwise: (-y, x)
-// rotate 90 degrees counter-clockwise: (y, -x)
+    // draw the upcoming pieces down the right hand side of the well, shrunk
+    // if need be so the whole queue fits beside it, and the hold box beside
+    // the other side
+    let queue_x = well_x as i32 + (state.board.width() as u32 * tile_size) as i32 + 20;
+    let (box_columns, box_rows) = preview_box(&state.options.pieces);
+    let preview_tile = (tile_size * 3 / 4)
+        .min((width - queue_x as u32 - 10) / box_columns)
+        .min((height - 110) / (state.next_pieces.len().max(1) as u32 * box_rows))
+        .min((well_x - 20) / box_columns);
+    let (box_width, box_height) = (box_columns * preview_tile, box_rows * preview_tile);
+    for (i, piece) in state.next_pieces.iter().enumerate() {
+        let area = Rect::new(queue_x, 110 + (i as u32 * box_height) as i32, box_width, box_height);
+        draw_piece_in_box(state.options.pieces.blocks(piece), area, preview_tile, None, &palette, canvas);
+    }
 
-fn rotated_cw(piece: [[u8; 4]; 4]) -> [[u8; 4]; 4] {
-    let mut result = [[0; 4]; 4];
-    for y in 0..=3 {
-        for x in 0..=3 {
-            result[y][x] = piece[3-x][y]; // hmmm
-        }
+    // draw the held piece on the left, greyed out while hold is used up
+    if let Some(held) = state.held_piece {
+        let grey = if state.hold_used { Some(rgb!(120, 120, 120)) } else { None };
+        let area = Rect::new(10, 160, box_width, box_height);
+        draw_piece_in_box(state.options.pieces.blocks(&held), area, preview_tile, grey, &palette, canvas);
     }
-    result
-}
 
-fn can_rotate_cw(state: &State) -> bool {
-    // 1. try rotating it cw?
-    let rotated = rotated_cw(state.current_piece);
-    // 2. when you put it on the pivot offset after rotating,
-    //    does it hit a wall or another block?
-    // 3. if not, we're good.
-    return can_move_piece(&state, &rotated, 0, 0);
+    // done drawing, reset colour state
+    canvas.set_draw_color(rgb!(0, 0, 0));
 }
 
 fn render_text(x: i32, y: i32, text: String, font: &sdl2::ttf::Font, canvas: &mut WindowCanvas) { // FIXME
@@ -379,267 +287,6 @@ fn render_text_centered(y: i32, text: String, font: &sdl2::ttf::Font, canvas: &m
     canvas.copy(&t, src, Rect::new( x, y, src.width(), src.height() )).unwrap(); // TODO: is 0 right?
 }
 
-fn land_piece(state: &mut State) {
-    // TODO: need to reduplicate all of this code - some kind of special iterator
-    let (pivot_x, pivot_y) = find_pivot_offset(&state.current_piece);
-
-    for (cy, row) in state.current_piece.iter().enumerate() {
-        for (cx, cell) in row.iter().enumerate() {
-            if *cell > 0 {
-                let x : i32 = state.current_piece_x as i32 - pivot_x as i32 + cx as i32;
-                if x < 0 { continue; }
-                let y : i32 = state.current_piece_y as i32 - pivot_y as i32 + cy as i32;
-                if y < 0 { continue; } // bail out on this one if the cell is off screen
-
-                state.cells[y as usize][x as usize] = *cell;
-            }
-        }
-    }
-}
-
-fn random_piece() -> [[u8; 4]; 4] {
-    let mut rng = rand::thread_rng();
-    // pick a piece at random from our repertoire
-    // store the geometry as 1 except for the pivot which is 128 + 1
-    // mul the 'base' value of the piece (pay attention to pivots) by a palette value
-    // install the piece with a pivot
-    let pieces = [
-        [ // J
-            [ 1, 0, 0, 0 ],
-            [ 1, 129, 1, 0 ],
-            [ 0, 0, 0, 0 ],
-            [ 0, 0, 0, 0 ]
-        ],
-        [ // L
-            [ 0, 0, 0, 0 ],
-            [ 1, 129, 1, 0 ],
-            [ 1, 0, 0, 0 ],
-            [ 0, 0, 0, 0 ]
-        ],
-        [ // T
-            [ 0, 0, 0, 0 ],
-            [ 0, 1, 129, 1 ],
-            [ 0, 0, 1, 0 ],
-            [ 0, 0, 0, 0 ]
-        ],
-        [ // O
-            [ 0, 0, 0, 0 ],
-            [ 0, 129, 129, 0 ],
-            [ 0, 129, 129, 0 ],
-            [ 0, 0, 0, 0 ]
-            // this is a great hack -
-
-            // i had a problem before where i only had one pivot
-            // point, so it would seem to spin around the pivot.
-            // after some more thinking, i figured it doesn't just have
-            // to have only ONE pivot point - the pivot point search
-            // code only looks for the first pivot point before giving up,
-            // so after a rotation the 'new' top-left point will be that point.
-            // then the user just thinks it didn't rotate at all (even though it did)
-            // and we no longer have to write explicit "fix things after the fact"
-            // code.
-        ],
-        [ // I
-            [ 0, 0, 1, 0 ],
-            [ 0, 0, 129, 0 ],
-            [ 0, 0, 1, 0 ],
-            [ 0, 0, 1, 0 ]
-        ],
-        [ // Z
-            [ 0, 0, 0, 1 ],
-            [ 0, 0, 129, 1 ],
-            [ 0, 0, 1, 0 ],
-            [ 0, 0, 0, 0 ]
-        ],
-        [ // S
-            [ 0, 0, 1, 0 ],
-            [ 0, 0, 129, 1 ],
-            [ 0, 0, 0, 1 ],
-            [ 0, 0, 0, 0 ]
-        ],
-    ];
-
-    let i = (rng.next_u32() as usize) % pieces.len();
-    let src = pieces[i];
-
-    let mut result : [[u8; 4]; 4] = [ [0,0,0,0], [0,0,0,0], [0,0,0,0], [0,0,0,0] ]; // FIXME: shorthand?
-
-    //let colour = 1 + ((rng.next_u32() as usize) % 8) as u8; // HACK - get the palette global in here for exact length...
-    let colour = 1 + (i % 8) as u8; // HACK - get the palette global in here for exact length...
-
-    for y in 0..4 {
-        for x in 0..4 {
-            if src[y][x] > 128 {
-                result[y][x] = (src[y][x] - 128) * colour + 128;
-            } else {
-                result[y][x] = src[y][x] * colour;
-            }
-        }
-    }
-
-    result
-}
-
-fn rows_complete(state: &State) -> u32 {
-    let mut count = 0;
-    for row_idx in 0..state.cells.len() {
-        if state.cells[row_idx].iter().all(|&c| c > 0) {
-            count += 1; // this row is filled
-        }
-    }
-    count
-}
-
-fn clear_completed_rows(state: &mut State) {
-    // start from the top
-    for row_idx in 0..state.cells.len() {
-        if state.cells[row_idx].iter().all(|&c| c > 0) {
-            // zero out this row
-            for i in 0..state.cells[row_idx].len() {
-                state.cells[row_idx][i] = 0;
-            }
-
-            // if you see a full row, just copy all the rows above it down
-            if row_idx > 0 {
-                for copy_row_idx in (0..row_idx).rev() {
-                    state.cells[copy_row_idx + 1] = state.cells[copy_row_idx];
-                }
-            }
-        }
-    }
-    // FIXME: this feels really bad
-}
-
-#[test]
-fn test_clear_completed_rows() {
-    let mut state = State {
-        cells: [
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 1
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 2
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 3
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 4
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 5
-            [ 0, 0, 0, 1, 1, 1, 1, 0, 0, 0 ], // 6
-            [ 1, 1, 1, 1, 1, 1, 1, 1, 1, 1 ], // 7
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 8
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 9
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 10
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 11
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 12
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 13
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 14
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 15
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 16
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 17
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 18
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 19
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 20
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 21
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 22
-        ],
-        score: 0,
-        lines: 0,
-        level: 0,
-        current_piece_x: 4,
-        current_piece_y: 0,
-        current_piece: random_piece(),
-        next_piece: random_piece(),
-        step_time: 0.0,
-        dropping: false,
-        status: GameState::Playing
-    };
-    clear_completed_rows(&mut state);
-    assert_eq!(state.cells,
-        // ensure row 6 drops into row 7 which is obliterated
-        [
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 1
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 2
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 3
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 4
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 5
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 6
-            [ 0, 0, 0, 1, 1, 1, 1, 0, 0, 0 ], // 7
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 8
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 9
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 10
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 11
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 12
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 13
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 14
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 15
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 16
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 17
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 18
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 19
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 20
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 21
-            [ 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ], // 22
-        ]
-    );
-}
-
-fn on_piece_landed(state: &mut State) {
-    // detect scoring (1, 2, 3, 4, etc)
-    let rows_completed = rows_complete(state);
-    if rows_completed > 0 {
-        // switch to scoring animations if any scores were made
-        state.status = GameState::ClearingRows(10.0);
-        // 500 points per row
-        state.score += rows_completed * (state.level as u32 + 1) * 500;
-
-        // level up every 10 lines
-        if (state.lines + rows_completed as u16) / 10 > (state.lines / 10) {
-            state.level += 1;
-        }
-
-        state.lines += rows_completed as u16;
-    }
-
-    // set up the next piece
-    //  - swap next piece into new piece
-    state.current_piece = state.next_piece;
-    //  - compute next piece
-    state.next_piece = random_piece();
-    //  - reset cursor position
-    state.current_piece_y = 0;
-    state.current_piece_x = 4;
-}
-
-fn step_piece(state: &mut State) {
-    if piece_will_land(&state) {
-        if piece_will_lose(&state) {
-            // detect losing
-            state.status = GameState::GameOver;
-            state.step_time = 0.0;
-        } else {
-            // write the piece to the state
-            land_piece(state);
-            on_piece_landed(state);
-        }
-    } else {
-        // drop the piece
-        state.current_piece_y += 1;
-    }
-}
-
-impl State {
-    fn new() -> State {
-        State {
-            cells: [[0; WELL_WIDTH]; WELL_HEIGHT],
-            score: 0,
-            lines: 0,
-            level: 0,
-            current_piece_x: 4,
-            current_piece_y: 0, // for now
-            current_piece: random_piece(),
-            next_piece: random_piece(),
-            step_time: 0.0,
-            dropping: false,
-            status: GameState::Playing
-        }
-    }
-}
-
 const DEFAULT_FPS : u32 = 30;
 
 pub struct FPSManager {
@@ -649,6 +296,12 @@ pub struct FPSManager {
     frame_count : u32,
 }
 
+impl Default for FPSManager {
+    fn default() -> Self {
+        FPSManager::new()
+    }
+}
+
 impl FPSManager {
     pub fn new() -> FPSManager {
         let now = Instant::now();
@@ -685,15 +338,132 @@ impl FPSManager {
             self.start_ticks = Instant::now();
         }
 
-        return time_passed;
+        time_passed
+    }
+}
+
+// how long a banner like "T-SPIN DOUBLE" stays up
+const BANNER_TIME : Duration = Duration::from_millis(1500);
+
+fn event_banner(event: &GameEvent) -> Option<String> {
+    match *event {
+        GameEvent::Clear { lines, spin, back_to_back } => {
+            let name = ["", " SINGLE", " DOUBLE", " TRIPLE", " QUAD"][(lines as usize).min(4)];
+            let text = match spin {
+                Spin::Full => format!("T-SPIN{}", name),
+                Spin::Mini => format!("T-SPIN MINI{}", name),
+                Spin::None if lines >= 4 => "TETRIS".to_string(),
+                Spin::None => return None
+            };
+            if back_to_back {
+                Some(format!("B2B {}", text))
+            } else {
+                Some(text)
+            }
+        },
+        GameEvent::PerfectClear { back_to_back: true, .. } => Some("B2B PERFECT CLEAR".to_string()),
+        GameEvent::PerfectClear { .. } => Some("PERFECT CLEAR".to_string()),
+        GameEvent::BoardReset(reason) => Some(reason.name().to_uppercase())
     }
 }
 
-// TODO: Make an iterator that does a 2D iteration over the current piece
-// and hits a callback for each valid square with (cx, cy, cell)?
-// Reduce the code everywhere.
+// m:ss.mmm
+fn format_time(time: Duration) -> String {
+    let ms = time.as_millis();
+    format!("{}:{:02}.{:03}", ms / 60_000, ms / 1000 % 60, ms % 1000)
+}
+
+// where the split times start, under the hold box
+const SPLITS_Y : i32 = 260;
+
+fn held_key(key: Keycode) -> Option<HeldKey> {
+    match key {
+        Keycode::Left => Some(HeldKey::Left),
+        Keycode::Right => Some(HeldKey::Right),
+        Keycode::Down => Some(HeldKey::SoftDrop),
+        _ => None
+    }
+}
+
+// Game options from the command line, e.g. `tetris --randomizer tgm`
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--randomizer" => options.randomizer = value()?.parse()?,
+            "--preview" => {
+                options.preview = value()?.parse().map_err(|_| "--preview needs a number".to_string())?;
+                if !(1..=MAX_PREVIEW).contains(&options.preview) {
+                    return Err(format!("--preview must be between 1 and {}", MAX_PREVIEW));
+                }
+            },
+            "--lock-delay" => options.lock_delay = value()?.parse().map_err(|_| "--lock-delay needs a number of seconds".to_string())?,
+            "--move-resets" => options.move_reset_limit = value()?.parse().map_err(|_| "--move-resets needs a number".to_string())?,
+            "--das" => options.das = value()?.parse::<f32>().map_err(|_| "--das needs a number of milliseconds".to_string())? / 1000.0,
+            "--arr" => options.arr = value()?.parse::<f32>().map_err(|_| "--arr needs a number of milliseconds".to_string())? / 1000.0,
+            "--gravity" => options.gravity = value()?.parse()?,
+            "--scoring" => options.scoring = value()?.parse()?,
+            "--mode" => options.mode = value()?.parse()?,
+            "--garbage" => options.dig_rows = value()?.parse().map_err(|_| "--garbage needs a number of rows".to_string())?,
+            "--messiness" => {
+                options.messiness = value()?.parse().map_err(|_| "--messiness needs a number".to_string())?;
+                if !(0.0..=1.0).contains(&options.messiness) {
+                    return Err("--messiness must be between 0 and 1".to_string());
+                }
+            },
+            "--time" => options.ultra_time = value()?.parse().map_err(|_| "--time needs a number of seconds".to_string())?,
+            "--lines" => {
+                options.sprint_lines = value()?.parse().map_err(|_| "--lines needs a number".to_string())?;
+                if options.sprint_lines == 0 {
+                    return Err("--lines must be at least 1".to_string());
+                }
+            },
+            "--pieces" => options.pieces = PieceSet::find(&value()?)?,
+            "--well" => {
+                let size = value()?;
+                let (w, h) = size.split_once('x').ok_or("--well needs a size like 10x20".to_string())?;
+                options.well_width = w.parse().map_err(|_| "--well needs a size like 10x20".to_string())?;
+                options.well_height = h.parse().map_err(|_| "--well needs a size like 10x20".to_string())?;
+                if options.well_width < 4 || options.well_height < 4 {
+                    return Err("--well must be at least 4x4".to_string());
+                }
+                if options.well_width > MAX_WELL_WIDTH || options.well_height > MAX_WELL_HEIGHT {
+                    return Err(format!("--well can't be bigger than {}x{}", MAX_WELL_WIDTH, MAX_WELL_HEIGHT));
+                }
+            },
+            "--seed" => options.seed = Some(value()?.parse().map_err(|_| "--seed needs a number".to_string())?),
+            _ => return Err(format!("unknown option '{}'", arg))
+        }
+    }
+    // every piece has to be able to spawn flat, whichever of --well and
+    // --pieces came first
+    if options.well_width < options.pieces.max_size() {
+        return Err(format!("--well must be at least {} wide for these pieces", options.pieces.max_size()));
+    }
+    if options.mode == GameMode::Dig {
+        // with no garbage the run is over on the first tick
+        if options.dig_rows == 0 {
+            return Err("--garbage must be at least 1".to_string());
+        }
+        // leave some room to play in
+        if options.dig_rows >= options.well_height {
+            return Err(format!("--garbage must be less than the well height ({})", options.well_height));
+        }
+    }
+    Ok(options)
+}
 
 fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
     let _audio_subsystem = sdl_context.audio().unwrap();
@@ -716,9 +486,11 @@ fn main() {
     let texture_creator = canvas.texture_creator();
 
     let mut framerate = FPSManager::new();
-    framerate.set_framerate(FRAMERATE_HZ); // set fixed framerate at 25hz
+    framerate.set_framerate(FRAMERATE_HZ); // only the render rate, the game always ticks at TICK_HZ
 
-    let mut state = State::new();
+    let mut state = State::with_options(&options);
+    let mut show_ghost = true;
+    let mut banner : Option<(String, Instant)> = None;
 
     let mut event_pump = sdl_context.event_pump().unwrap();
 
@@ -731,21 +503,93 @@ fn main() {
     'main: loop {
         canvas.clear();
 
-        if state.status != GameState::GameOver {
-            render_cells(&state, width, height, &backgrounds, &mut canvas);
-
-            render_text(10, 10, format!("Score: {}", state.score), &font, &mut canvas);
-            render_text(10, 35, format!("Lines: {}", state.lines), &font, &mut canvas);
-            render_text(10, 60, format!("Level: {}", state.level), &font, &mut canvas);
+        if matches!(state.status, GameState::Playing | GameState::ClearingRows(_)) {
+            render_cells(&state, show_ghost, width, height, &backgrounds, &mut canvas);
+
+            match state.options.mode {
+                GameMode::Ultra => {
+                    // the countdown matters more than the level here
+                    render_text(10, 10, format!("Time: {}", format_time(time_left(&state))), &font, &mut canvas);
+                    render_text(10, 35, format!("Score: {}", state.score), &font, &mut canvas);
+                    render_text(10, 60, format!("Lines: {}", state.lines), &font, &mut canvas);
+                },
+                GameMode::Dig => {
+                    render_text(10, 10, format!("Score: {}", state.score), &font, &mut canvas);
+                    render_text(10, 35, format!("Garbage: {}", garbage_rows_left(&state)), &font, &mut canvas);
+                    render_text(10, 60, format!("Level: {}", state.level), &font, &mut canvas);
+                },
+                GameMode::Sprint => {
+                    render_text(10, 10, format!("Score: {}", state.score), &font, &mut canvas);
+                    render_text(10, 35, format!("Lines: {}/{}", state.lines, state.options.sprint_lines), &font, &mut canvas);
+                    render_text(10, 60, format!("Level: {}", state.level), &font, &mut canvas);
+                },
+                GameMode::Marathon | GameMode::Zen => {
+                    render_text(10, 10, format!("Score: {}", state.score), &font, &mut canvas);
+                    render_text(10, 35, format!("Lines: {}", state.lines), &font, &mut canvas);
+                    render_text(10, 60, format!("Level: {}", state.level), &font, &mut canvas);
+                }
+            }
+            if state.combo > 0 {
+                render_text(10, 85, format!("Combo: {}", state.combo), &font, &mut canvas);
+            }
+            if state.back_to_back {
+                render_text(10, 110, "Back-to-Back".to_string(), &font, &mut canvas);
+            }
 
             // Next piece indicator
-            render_text(10, 85, "Next:".to_string(), &font, &mut canvas);
+            let well = well_rect(&state.board, width, height);
+            render_text(well.right() + 20, 85, "Next:".to_string(), &font, &mut canvas);
+            render_text(10, 135, "Hold:".to_string(), &font, &mut canvas);
+            if state.options.mode == GameMode::Sprint || state.options.mode == GameMode::Dig {
+                render_text(well.right() + 20, 10, format!("Time: {}", format_time(state.game_time)), &font, &mut canvas);
+                for (i, split) in state.splits.iter().enumerate() {
+                    let lines = (i as u16 + 1) * SPLIT_LINES;
+                    render_text(10, SPLITS_Y + i as i32 * 25, format!("{}: {}", lines, format_time(*split)), &font, &mut canvas);
+                }
+            }
             // Will be rendered by the main piece renderer (FIXME: palette should be moved out of draw...)
+
+            // flash up anything special that just happened
+            for event in take_events(&mut state) {
+                if let Some(text) = event_banner(&event) {
+                    banner = Some((text, Instant::now()));
+                }
+            }
+            if let Some((text, shown)) = &banner {
+                if shown.elapsed() < BANNER_TIME {
+                    render_text_centered(well.y() + 40, text.clone(), &font, &mut canvas);
+                }
+            }
+        }
+        else if let GameState::GameOver(reason) = state.status {
+            draw_well(&state.board, width, height, state.level, &backgrounds, &mut canvas);
+            render_text_centered(height as i32 / 2 - 25, "Game Over".to_string(), &font, &mut canvas);
+            render_text_centered(height as i32 / 2, reason.name().to_string(), &font, &mut canvas);
+            render_text_centered(height as i32 / 2 + 25, format!("Final Score: {} ({} scoring)", state.score, state.options.scoring.rule().name()), &font, &mut canvas);
+            render_text_centered(height as i32 / 2 + 50, format!("Seed: {}", state.seed), &font, &mut canvas);
         }
         else {
-            draw_well(width, height, state.level, &backgrounds, &mut canvas);
-            render_text_centered(height as i32 / 2, "Game Over".to_string(), &font, &mut canvas);
-            render_text_centered(height as i32 / 2 + 25, format!("Final Score: {}", state.score), &font, &mut canvas);
+            // made it to the end, show how it went
+            draw_well(&state.board, width, height, state.level, &backgrounds, &mut canvas);
+            let mut y = height as i32 / 2 - 75;
+            let mut lines = vec![format!("{} Complete", state.options.mode.name())];
+            if state.options.mode == GameMode::Ultra {
+                // ranked on score rather than time
+                lines.push(format!("Score: {} ({} scoring)", state.score, state.options.scoring.rule().name()));
+                lines.push(format!("Lines: {}", state.lines));
+            } else {
+                lines.push(format!("Time: {}", format_time(state.game_time)));
+            }
+            lines.push(format!("Pieces: {}", state.pieces_placed));
+            lines.push(format!("PPS: {:.2}", pieces_per_second(&state)));
+            for (i, split) in state.splits.iter().enumerate() {
+                lines.push(format!("{} lines: {}", (i as u16 + 1) * SPLIT_LINES, format_time(*split)));
+            }
+            lines.push(format!("Seed: {}", state.seed));
+            for text in lines {
+                render_text_centered(y, text, &font, &mut canvas);
+                y += 25;
+            }
         }
 
         canvas.present();
@@ -760,99 +604,65 @@ fn main() {
                             keycode: Some(Keycode::Escape), ..
                         } => break 'main,
                         Event::KeyDown {
-                            keycode: Some(key), ..
+                            keycode: Some(key), repeat: false, ..
                         } => {
+                            // auto repeat is up to the engine, not the OS
+                            if let Some(held) = held_key(key) {
+                                key_down(&mut state, held);
+                            }
+
                             match key {
-                                Keycode::Space => {
-                                    // TODO: block can rotate
-                                    if can_rotate_cw(&state) {
-                                        // TODO: wallkicks?
-                                        state.current_piece = rotated_cw(state.current_piece);
-                                    }
-                                },
-                                Keycode::Left => {
-                                    if can_move_left(&state) {
-                                        state.current_piece_x -= 1;
-                                    }
-                                },
-                                Keycode::Right => {
-                                    if can_move_right(&state) {
-                                        state.current_piece_x += 1;
-                                    }
-                                },
-                                Keycode::Down => {
-                                    state.dropping = true;
-                                },
+                                Keycode::Space | Keycode::X => { rotate_cw(&mut state); },
+                                Keycode::Z => { rotate_ccw(&mut state); },
+                                Keycode::A => { rotate_180(&mut state); },
+                                Keycode::Up => hard_drop(&mut state),
+                                Keycode::C | Keycode::LShift => { hold_piece(&mut state); },
+                                Keycode::G => show_ghost = !show_ghost,
+                                // zen only, other modes never save anything to undo
+                                Keycode::U | Keycode::Backspace => { undo(&mut state); },
+                                Keycode::N if state.options.mode == GameMode::Zen => cycle_next_piece(&mut state, 1),
+                                Keycode::B if state.options.mode == GameMode::Zen => cycle_next_piece(&mut state, -1),
                                 _ => {}
                             }
                         },
                         Event::KeyUp {
                             keycode: Some(key), ..
                         } => {
-                            match key {
-                                Keycode::Down => {
-                                    state.dropping = false;
-                                },
-                                _ => {}
+                            if let Some(held) = held_key(key) {
+                                key_up(&mut state, held);
                             }
                         },
                         _ => {}
                     }
                 }
-
-                let mut step_tick = 2.5 + ((state.level + 1) as f32 * 1.5);
-
-                if state.dropping {
-                    step_tick *= 10.0; // drop faster when DOWN is held
-                }
-
-                state.step_time += step_tick;
-
-                // TODO: adjust this 'speed' based on the level
-                while state.step_time >= 50.0 { // ehh, i don't like this while
-                    state.step_time -= 50.0;
-                    step_piece(&mut state);
-                }
             },
-            GameState::ClearingRows(mut timer) => {
-                timer -= 0.55;
-                if timer <= 0.0 {
-                    // clearing complete, return to game
-                    state.status = GameState::Playing;
-
-                    // delete the cleared rows!!!
-                    clear_completed_rows(&mut state);
-                }
-                else {
-                    // still clearing, step the timer down
-                    state.status = GameState::ClearingRows(timer);
-                }
-
-                // stub event pump, just to keep the OS happy
+            GameState::ClearingRows(_) => {
+                // nothing to move, but held keys still count so DAS can charge
                 for event in event_pump.poll_iter() {
                     match event {
                         Event::Quit {..} => break 'main,
                         Event::KeyDown {
                             keycode: Some(Keycode::Escape), ..
                         } => break 'main,
+                        Event::KeyDown {
+                            keycode: Some(key), repeat: false, ..
+                        } => {
+                            if let Some(held) = held_key(key) {
+                                key_down(&mut state, held);
+                            }
+                        },
                         Event::KeyUp {
                             keycode: Some(key), ..
                         } => {
-                            match key {
-                                Keycode::Down => {
-                                    state.dropping = false;
-                                },
-                                _ => {}
+                            if let Some(held) = held_key(key) {
+                                key_up(&mut state, held);
                             }
-                        }
-                        // FIXME: reset dropping state if key up here
+                        },
                         _ => {}
                     }
                 }
             },
-            GameState::GameOver => {
-                state.step_time += 5.0;
-
+            GameState::GameOver(_) | GameState::Finished => {
                 for event in event_pump.poll_iter() {
                     match event {
                         Event::Quit {..} => break 'main,
@@ -864,8 +674,8 @@ fn main() {
                                 Keycode::Escape => break 'main,
                                 _ => {
                                     // restart game
-                                    if state.step_time >= 250.0 {
-                                        state = State::new(); // restart the game
+                                    if can_restart(&state) {
+                                        state = State::with_options(&options); // restart the game
                                     }
                                 }
                             }
@@ -876,6 +686,7 @@ fn main() {
             }
         }
 
-        framerate.delay();
+        let elapsed = framerate.delay();
+        update(&mut state, elapsed);
     }
 }
@@ -0,0 +1,38 @@
+use std::str::FromStr;
+
+// What the player is trying to do, and what ends the game (besides topping out)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameMode {
+    Marathon, // the original: keep going until you top out
+    Sprint, // clear a set number of lines as fast as possible
+    Ultra, // score as much as possible before time runs out
+    Dig, // clear away rows of garbage as fast as possible
+    Zen // practice: no topping out, undo, and pick the next piece
+}
+
+impl GameMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            GameMode::Marathon => "Marathon",
+            GameMode::Sprint => "Sprint",
+            GameMode::Ultra => "Ultra",
+            GameMode::Dig => "Dig",
+            GameMode::Zen => "Zen"
+        }
+    }
+}
+
+impl FromStr for GameMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "marathon" => Ok(GameMode::Marathon),
+            "sprint" => Ok(GameMode::Sprint),
+            "ultra" => Ok(GameMode::Ultra),
+            "dig" | "cheese" => Ok(GameMode::Dig),
+            "zen" => Ok(GameMode::Zen),
+            _ => Err(format!("unknown mode '{}' (try marathon, sprint, ultra, dig or zen)", s))
+        }
+    }
+}
@@ -0,0 +1,427 @@
+// Pieces are defined by a piece set, loaded from a text file (see
+// pieces/standard.txt for the format). Every piece lives in a box and has a
+// shape for each of the four rotation states. If the rotated piece doesn't fit,
+// each offset from its kick table is tried in order before giving up.
+
+use crate::board::GARBAGE;
+use std::fs;
+use std::rc::Rc;
+
+// which piece in the set, in the order they're defined
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PieceKind(pub usize);
+
+// the standard set, in the order pieces/standard.txt defines them
+impl PieceKind {
+    pub const J : PieceKind = PieceKind(0);
+    pub const L : PieceKind = PieceKind(1);
+    pub const T : PieceKind = PieceKind(2);
+    pub const O : PieceKind = PieceKind(3);
+    pub const I : PieceKind = PieceKind(4);
+    pub const Z : PieceKind = PieceKind(5);
+    pub const S : PieceKind = PieceKind(6);
+}
+
+pub const ALL_PIECES : [PieceKind; 7] = [
+    PieceKind::J, PieceKind::L, PieceKind::T, PieceKind::O, PieceKind::I, PieceKind::Z, PieceKind::S
+];
+
+const STANDARD_SET : &str = include_str!("../pieces/standard.txt");
+const PENTRIS_SET : &str = include_str!("../pieces/pentris.txt");
+
+// The four rotation states, usually written 0, R, 2 and L
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rotation {
+    Spawn,
+    Right,
+    Reverse,
+    Left
+}
+
+const ALL_ROTATIONS : [Rotation; 4] = [Rotation::Spawn, Rotation::Right, Rotation::Reverse, Rotation::Left];
+
+impl Rotation {
+    pub fn cw(self) -> Rotation {
+        match self {
+            Rotation::Spawn => Rotation::Right,
+            Rotation::Right => Rotation::Reverse,
+            Rotation::Reverse => Rotation::Left,
+            Rotation::Left => Rotation::Spawn
+        }
+    }
+
+    pub fn ccw(self) -> Rotation {
+        self.cw().cw().cw()
+    }
+
+    pub fn flipped(self) -> Rotation {
+        self.cw().cw()
+    }
+
+    // number of clockwise quarter turns away from spawn
+    pub fn turns(self) -> usize {
+        match self {
+            Rotation::Spawn => 0,
+            Rotation::Right => 1,
+            Rotation::Reverse => 2,
+            Rotation::Left => 3
+        }
+    }
+
+    fn parse(s: &str) -> Option<Rotation> {
+        match s {
+            "0" => Some(Rotation::Spawn),
+            "R" => Some(Rotation::Right),
+            "2" => Some(Rotation::Reverse),
+            "L" => Some(Rotation::Left),
+            _ => None
+        }
+    }
+}
+
+// offsets as (dx, dy) to try when turning from one state to another, y pointing down the well
+#[derive(Clone, Debug)]
+pub struct Kicks {
+    pub from: Rotation,
+    pub to: Rotation,
+    pub offsets: Vec<(i32, i32)>
+}
+
+#[derive(Clone, Debug)]
+pub struct KickTable {
+    pub name: String,
+    pub kicks: Vec<Kicks>
+}
+
+#[derive(Clone, Debug)]
+pub struct PieceDef {
+    pub name: String,
+    pub colour: u8, // palette index used for this piece's blocks
+    pub shapes: [Vec<(i32, i32)>; 4], // blocks for each rotation state, relative to the top-left of the box
+    pub size: usize, // width of the square box it turns in
+    pub t_spin: bool, // gets checked for T-spins
+    pub kicks: Option<usize>, // index into the set's kick tables, None only rotates in place
+    pub spawn_offset: (i32, i32)
+}
+
+#[derive(Clone, Debug)]
+pub struct PieceSet {
+    pub pieces: Vec<PieceDef>,
+    pub kick_tables: Vec<KickTable>
+}
+
+const NO_KICKS : [(i32, i32); 1] = [ (0, 0) ];
+
+// rotate 90 degrees clockwise within the top-left size x size box
+fn rotated_cw(blocks: &[(i32, i32)], size: i32) -> Vec<(i32, i32)> {
+    let mut v : Vec<(i32, i32)> = blocks.iter().map(|&(x, y)| (size - 1 - y, x)).collect();
+    v.sort_by_key(|&(x, y)| (y, x));
+    v
+}
+
+fn parse_offset(s: &str) -> Option<(i32, i32)> {
+    let (x, y) = s.split_once(',')?;
+    Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+}
+
+fn is_shape_row(line: &str) -> bool {
+    line.chars().all(|c| c == '.' || c == '#')
+}
+
+// what's being read right now
+enum Section {
+    Nothing,
+    Kicks,
+    Piece
+}
+
+struct PieceBuilder {
+    name: String,
+    colour: Option<u8>,
+    kicks: Option<usize>,
+    spawn_offset: (i32, i32),
+    t_spin: bool,
+    // rows of each state as written, and the state being read
+    rows: [Vec<String>; 4],
+    reading: Option<Rotation>
+}
+
+impl PieceBuilder {
+    fn build(self) -> Result<PieceDef, String> {
+        let colour = self.colour.ok_or(format!("piece {} has no colour", self.name))?;
+        if self.rows[0].is_empty() {
+            return Err(format!("piece {} has no shape", self.name));
+        }
+
+        let mut shapes : [Vec<(i32, i32)>; 4] = Default::default();
+        for (i, rows) in self.rows.iter().enumerate() {
+            for (y, row) in rows.iter().enumerate() {
+                for (x, c) in row.chars().enumerate() {
+                    if c == '#' {
+                        shapes[i].push((x as i32, y as i32));
+                    }
+                }
+            }
+        }
+        if shapes[0].is_empty() {
+            return Err(format!("piece {} has no blocks", self.name));
+        }
+
+        // fill in any missing states by turning the previous one in its box
+        let size = self.rows[0].len().max(self.rows[0].iter().map(|r| r.len()).max().unwrap_or(0));
+        for i in 1..4 {
+            if self.rows[i].is_empty() {
+                shapes[i] = rotated_cw(&shapes[i - 1], size as i32);
+            } else if shapes[i].len() != shapes[0].len() {
+                return Err(format!("piece {} has a different number of blocks in state {:?}", self.name, ALL_ROTATIONS[i]));
+            }
+        }
+        // the 3-corner rule looks at the corners of a 3x3 box
+        if self.t_spin && size != 3 {
+            return Err(format!("piece {} can't be a tspin piece, it needs a 3x3 box", self.name));
+        }
+
+        Ok(PieceDef {
+            name: self.name,
+            colour,
+            shapes,
+            size,
+            t_spin: self.t_spin,
+            kicks: self.kicks,
+            spawn_offset: self.spawn_offset
+        })
+    }
+}
+
+impl PieceSet {
+    // the seven tetrominoes with SRS
+    pub fn standard() -> Rc<PieceSet> {
+        Rc::new(PieceSet::parse(STANDARD_SET).expect("built-in piece set is broken"))
+    }
+
+    // the 18 one-sided pentominoes
+    pub fn pentris() -> Rc<PieceSet> {
+        Rc::new(PieceSet::parse(PENTRIS_SET).expect("built-in piece set is broken"))
+    }
+
+    // one of the sets above by name, or a file to load
+    pub fn find(name: &str) -> Result<Rc<PieceSet>, String> {
+        match name {
+            "standard" | "tetris" => Ok(PieceSet::standard()),
+            "pentris" => Ok(PieceSet::pentris()),
+            path => PieceSet::load(path)
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Rc<PieceSet>, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+        PieceSet::parse(&text).map(Rc::new).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn parse(text: &str) -> Result<PieceSet, String> {
+        let mut set = PieceSet { pieces: Vec::new(), kick_tables: Vec::new() };
+        let mut section = Section::Nothing;
+        let mut piece : Option<PieceBuilder> = None;
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let err = |msg: &str| format!("line {}: {}", n + 1, msg);
+            let mut words = line.split_whitespace();
+            let word = words.next().unwrap();
+
+            // shape rows belong to whatever rotation state was last named
+            if let (Section::Piece, Some(builder)) = (&section, piece.as_mut()) {
+                if is_shape_row(line) {
+                    let rotation = builder.reading.ok_or_else(|| err("shape row before a rotation state"))?;
+                    builder.rows[rotation.turns()].push(line.to_string());
+                    continue;
+                }
+                if let Some(rotation) = Rotation::parse(line) {
+                    builder.reading = Some(rotation);
+                    continue;
+                }
+            }
+
+            match word {
+                "kicks" if !matches!(section, Section::Piece) => {
+                    let name = words.next().ok_or_else(|| err("kick table needs a name"))?;
+                    set.kick_tables.push(KickTable { name: name.to_string(), kicks: Vec::new() });
+                    section = Section::Kicks;
+                },
+                "kicks" => {
+                    let name = words.next().ok_or_else(|| err("kicks needs a table name"))?;
+                    let index = set.kick_tables.iter().position(|t| t.name == name)
+                        .ok_or_else(|| err(&format!("no kick table called {}", name)))?;
+                    piece.as_mut().unwrap().kicks = Some(index);
+                },
+                "piece" => {
+                    let name = words.next().ok_or_else(|| err("piece needs a name"))?;
+                    if let Some(builder) = piece.take() {
+                        set.pieces.push(builder.build()?);
+                    }
+                    piece = Some(PieceBuilder {
+                        name: name.to_string(),
+                        colour: None,
+                        kicks: None,
+                        spawn_offset: (0, 0),
+                        t_spin: false,
+                        rows: Default::default(),
+                        reading: None
+                    });
+                    section = Section::Piece;
+                },
+                "colour" | "color" if matches!(section, Section::Piece) => {
+                    let colour = words.next().and_then(|c| c.parse().ok()).ok_or_else(|| err("colour needs a number"))?;
+                    // 0 is an empty cell and GARBAGE is taken
+                    if !(1..GARBAGE).contains(&colour) {
+                        return Err(err(&format!("colour must be between 1 and {}", GARBAGE - 1)));
+                    }
+                    piece.as_mut().unwrap().colour = Some(colour);
+                },
+                "tspin" if matches!(section, Section::Piece) => piece.as_mut().unwrap().t_spin = true,
+                "spawn" if matches!(section, Section::Piece) => {
+                    let dx = words.next().and_then(|c| c.parse().ok());
+                    let dy = words.next().and_then(|c| c.parse().ok());
+                    match (dx, dy) {
+                        (Some(dx), Some(dy)) => piece.as_mut().unwrap().spawn_offset = (dx, dy),
+                        _ => return Err(err("spawn needs two numbers"))
+                    }
+                },
+                transition if matches!(section, Section::Kicks) => {
+                    let (from, to) = transition.split_once('>')
+                        .and_then(|(a, b)| Some((Rotation::parse(a)?, Rotation::parse(b)?)))
+                        .ok_or_else(|| err(&format!("expected a rotation like 0>R, not {}", transition)))?;
+                    let offsets = words.map(parse_offset).collect::<Option<Vec<_>>>()
+                        .ok_or_else(|| err("kick offsets look like x,y"))?;
+                    set.kick_tables.last_mut().unwrap().kicks.push(Kicks { from, to, offsets });
+                },
+                _ => return Err(err(&format!("don't know what to do with '{}'", line)))
+            }
+        }
+
+        if let Some(builder) = piece.take() {
+            set.pieces.push(builder.build()?);
+        }
+        if set.pieces.is_empty() {
+            return Err("no pieces in the set".to_string());
+        }
+        Ok(set)
+    }
+
+    pub fn def(&self, kind: PieceKind) -> &PieceDef {
+        &self.pieces[kind.0]
+    }
+
+    // the piece called `name`, if the set has one
+    pub fn kind_named(&self, name: &str) -> Option<PieceKind> {
+        self.pieces.iter().position(|p| p.name == name).map(PieceKind)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pieces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pieces.is_empty()
+    }
+
+    // (x, y, colour) for every block, relative to the top-left of the box
+    pub fn blocks(&self, piece: &Piece) -> Vec<(i32, i32, u8)> {
+        let def = self.def(piece.kind);
+        def.shapes[piece.rotation.turns()].iter().map(|&(x, y)| (x, y, def.colour)).collect()
+    }
+
+    // the widest box in the set, for sizing the preview
+    pub fn max_size(&self) -> usize {
+        self.pieces.iter().map(|p| p.size).max().unwrap_or(0)
+    }
+
+    // the offsets to try, in order, when rotating `kind` from one state to another
+    pub fn kicks(&self, kind: PieceKind, from: Rotation, to: Rotation) -> &[(i32, i32)] {
+        self.def(kind).kicks
+            .and_then(|t| self.kick_tables[t].kicks.iter().find(|k| k.from == from && k.to == to))
+            .map_or(&NO_KICKS, |k| &k.offsets)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Piece {
+    pub kind: PieceKind,
+    pub rotation: Rotation
+}
+
+impl Piece {
+    pub fn new(kind: PieceKind) -> Piece {
+        Piece { kind, rotation: Rotation::Spawn }
+    }
+
+    pub fn rotated_cw(&self) -> Piece {
+        Piece { kind: self.kind, rotation: self.rotation.cw() }
+    }
+
+    pub fn rotated_ccw(&self) -> Piece {
+        Piece { kind: self.kind, rotation: self.rotation.ccw() }
+    }
+
+    pub fn rotated_180(&self) -> Piece {
+        Piece { kind: self.kind, rotation: self.rotation.flipped() }
+    }
+}
+
+#[test]
+fn test_four_rotations_come_back_around() {
+    let set = PieceSet::standard();
+    assert_eq!(set.len(), ALL_PIECES.len());
+    for kind in ALL_PIECES.iter() {
+        let piece = Piece::new(*kind);
+        let spun = piece.rotated_cw().rotated_cw().rotated_cw().rotated_cw();
+        assert_eq!(spun, piece);
+        assert_eq!(piece.rotated_ccw(), piece.rotated_cw().rotated_180());
+        assert_eq!(set.blocks(&spun), set.blocks(&piece));
+        assert_eq!(set.blocks(&piece).len(), 4);
+        assert_eq!(set.def(*kind).colour as usize, kind.0 + 1);
+    }
+    assert_eq!(set.def(PieceKind::T).name, "T");
+    assert!(set.def(PieceKind::T).t_spin);
+    assert_eq!(set.def(PieceKind::I).size, 4);
+}
+
+#[test]
+fn test_pentris_has_every_pentomino() {
+    let set = PieceSet::pentris();
+    assert_eq!(set.len(), 18);
+    assert_eq!(set.max_size(), 5);
+    for (i, def) in set.pieces.iter().enumerate() {
+        assert!(!def.t_spin);
+        let mut piece = Piece::new(PieceKind(i));
+        for _ in 0..4 {
+            let blocks = set.blocks(&piece);
+            assert_eq!(blocks.len(), 5, "{}", def.name);
+            assert!(blocks.iter().all(|&(x, y, _)| x >= 0 && y >= 0 && x < def.size as i32 && y < def.size as i32));
+            piece = piece.rotated_cw();
+        }
+    }
+}
+
+#[test]
+fn test_t_rotates_about_its_centre() {
+    let set = PieceSet::standard();
+    let t = Piece::new(PieceKind::T).rotated_cw();
+    assert_eq!(set.blocks(&t), vec![(1, 0, 3), (1, 1, 3), (2, 1, 3), (1, 2, 3)]);
+    assert_eq!(set.kicks(PieceKind::T, Rotation::Spawn, Rotation::Right)[1], (-1, 0));
+    assert_eq!(set.kicks(PieceKind::O, Rotation::Spawn, Rotation::Right), &[(0, 0)]);
+}
+
+#[test]
+fn test_parse_errors_say_where() {
+    let err = PieceSet::parse("piece X\ncolour 1\n0\n##\nwobble\n").unwrap_err();
+    assert!(err.starts_with("line 5:"), "{}", err);
+    assert!(PieceSet::parse("piece X\n0\n#\n").is_err()); // no colour
+    assert!(PieceSet::parse("piece X\ncolour 1\ntspin\n0\n##\n##\n").is_err()); // 2x2 box
+    let err = PieceSet::parse("piece X\ncolour 255\n0\n#\n").unwrap_err();
+    assert!(err.starts_with("line 2:"), "{}", err);
+    assert!(PieceSet::parse("piece X\ncolour 0\n0\n#\n").is_err());
+}
@@ -0,0 +1,209 @@
+use crate::rand::prelude::*;
+use crate::piece::*;
+use std::str::FromStr;
+
+// Decides which piece comes next, out of a set of `count` pieces. The random
+// numbers come from the caller so the same generator can be shared (and
+// seeded) across the whole game.
+pub trait Randomizer: RandomizerClone {
+    fn next(&mut self, rng: &mut dyn RngCore) -> PieceKind;
+}
+
+// so a whole game can be copied (for undo), randomizer and all
+pub trait RandomizerClone {
+    fn clone_box(&self) -> Box<dyn Randomizer>;
+}
+
+impl<T: 'static + Randomizer + Clone> RandomizerClone for T {
+    fn clone_box(&self) -> Box<dyn Randomizer> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Randomizer> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+fn any_piece(rng: &mut dyn RngCore, count: usize) -> PieceKind {
+    PieceKind(rng.gen_range(0, count))
+}
+
+// Every piece equally likely every time - the original behaviour
+#[derive(Clone)]
+pub struct Uniform {
+    count: usize
+}
+
+impl Randomizer for Uniform {
+    fn next(&mut self, rng: &mut dyn RngCore) -> PieceKind {
+        any_piece(rng, self.count)
+    }
+}
+
+// Shuffle `copies` of each piece into a bag and deal them out until it's empty
+#[derive(Clone)]
+pub struct Bag {
+    copies: usize,
+    count: usize,
+    bag: Vec<PieceKind>
+}
+
+impl Bag {
+    pub fn new(copies: usize, count: usize) -> Bag {
+        Bag { copies, count, bag: Vec::new() }
+    }
+}
+
+impl Randomizer for Bag {
+    fn next(&mut self, rng: &mut dyn RngCore) -> PieceKind {
+        if self.bag.is_empty() {
+            for _ in 0..self.copies {
+                self.bag.extend((0..self.count).map(PieceKind));
+            }
+            self.bag.shuffle(rng);
+        }
+        self.bag.pop().unwrap()
+    }
+}
+
+// NES: roll an 8-sided die, and if it comes up as the extra side or repeats
+// the last piece, roll once more with a 7-sided die and take whatever that says
+#[derive(Clone)]
+pub struct Nes {
+    count: usize,
+    last: Option<PieceKind>
+}
+
+impl Nes {
+    pub fn new(count: usize) -> Nes {
+        Nes { count, last: None }
+    }
+}
+
+impl Randomizer for Nes {
+    fn next(&mut self, rng: &mut dyn RngCore) -> PieceKind {
+        let roll = rng.gen_range(0, self.count + 1);
+        let piece = if roll == self.count || Some(PieceKind(roll)) == self.last {
+            any_piece(rng, self.count)
+        } else {
+            PieceKind(roll)
+        };
+        self.last = Some(piece);
+        piece
+    }
+}
+
+// TGM: remember the last 4 pieces and roll up to 6 times for one that isn't
+// among them. The first piece is never S, Z or O. Those are found by name, so
+// sets without pieces called I, J, L, T, S and Z (pentris, say) skip that rule
+// and start with an empty history.
+#[derive(Clone)]
+pub struct Tgm {
+    count: usize,
+    history: [Option<PieceKind>; 4],
+    starters: Vec<PieceKind>,
+    first: bool
+}
+
+const TGM_ROLLS : usize = 6;
+
+impl Tgm {
+    pub fn new(set: &PieceSet) -> Tgm {
+        let named = |names: &[&str]| names.iter().map(|name| set.kind_named(name)).collect::<Option<Vec<_>>>();
+        let (history, starters) = match (named(&["Z", "S"]), named(&["I", "J", "L", "T"])) {
+            (Some(zs), Some(starters)) => ([Some(zs[0]), Some(zs[1]), Some(zs[0]), Some(zs[1])], starters),
+            _ => ([None; 4], Vec::new())
+        };
+        Tgm { count: set.len(), history, starters, first: true }
+    }
+}
+
+impl Randomizer for Tgm {
+    fn next(&mut self, rng: &mut dyn RngCore) -> PieceKind {
+        let piece = if self.first {
+            self.first = false;
+            if self.starters.is_empty() {
+                any_piece(rng, self.count)
+            } else {
+                self.starters[rng.gen_range(0, self.starters.len())]
+            }
+        } else {
+            let mut piece = any_piece(rng, self.count);
+            for _ in 1..TGM_ROLLS {
+                if !self.history.contains(&Some(piece)) {
+                    break;
+                }
+                piece = any_piece(rng, self.count);
+            }
+            piece
+        };
+
+        self.history.rotate_left(1);
+        self.history[3] = Some(piece);
+        piece
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RandomizerKind {
+    Uniform,
+    Bag7,
+    Bag14,
+    Nes,
+    Tgm
+}
+
+impl RandomizerKind {
+    // a randomizer dealing from the pieces in `set`
+    pub fn build(self, set: &PieceSet) -> Box<dyn Randomizer> {
+        let count = set.len();
+        match self {
+            RandomizerKind::Uniform => Box::new(Uniform { count }),
+            RandomizerKind::Bag7 => Box::new(Bag::new(1, count)),
+            RandomizerKind::Bag14 => Box::new(Bag::new(2, count)),
+            RandomizerKind::Nes => Box::new(Nes::new(count)),
+            RandomizerKind::Tgm => Box::new(Tgm::new(set))
+        }
+    }
+}
+
+impl FromStr for RandomizerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform" => Ok(RandomizerKind::Uniform),
+            "7bag" | "bag7" => Ok(RandomizerKind::Bag7),
+            "14bag" | "bag14" => Ok(RandomizerKind::Bag14),
+            "nes" => Ok(RandomizerKind::Nes),
+            "tgm" => Ok(RandomizerKind::Tgm),
+            _ => Err(format!("unknown randomizer '{}' (try uniform, 7bag, 14bag, nes or tgm)", s))
+        }
+    }
+}
+
+#[test]
+fn test_bag_deals_every_piece_once() {
+    let mut rng = rand::thread_rng();
+    let mut bag = Bag::new(1, ALL_PIECES.len());
+    for _ in 0..3 {
+        let mut dealt : Vec<PieceKind> = (0..7).map(|_| bag.next(&mut rng)).collect();
+        dealt.sort_by_key(|k| k.0);
+        assert_eq!(dealt, ALL_PIECES.to_vec());
+    }
+}
+
+#[test]
+fn test_tgm_never_starts_with_s_z_or_o() {
+    let mut rng = rand::thread_rng();
+    let standard = PieceSet::standard();
+    for _ in 0..100 {
+        let first = Tgm::new(&standard).next(&mut rng);
+        assert!(![PieceKind::S, PieceKind::Z, PieceKind::O].contains(&first));
+    }
+
+    // pentris has an I and a Z but they aren't the tetrominoes
+    assert!(Tgm::new(&PieceSet::pentris()).starters.is_empty());
+}
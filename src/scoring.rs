@@ -0,0 +1,178 @@
+use crate::engine::Spin;
+use std::str::FromStr;
+
+// Everything a scoring rule might want to know about a piece that just locked
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Clear {
+    pub lines: u32,
+    pub spin: Spin,
+    pub back_to_back: bool, // a tetris or T-spin straight after another one
+    pub combo: i32, // -1 if this piece broke the combo, otherwise 0, 1, 2...
+    pub level: u16
+}
+
+pub trait ScoringRule {
+    fn name(&self) -> &'static str;
+    // points for locking a piece, whether or not it cleared anything
+    fn clear_points(&self, clear: &Clear) -> u32;
+    fn soft_drop_points(&self, rows: u32) -> u32;
+    fn hard_drop_points(&self, rows: u32) -> u32;
+    // extra points when a clear leaves the well completely empty
+    fn perfect_clear_points(&self, _clear: &Clear) -> u32 {
+        0
+    }
+}
+
+// The original rule: 500 points a row, times the level
+pub struct FlatScoring;
+
+impl ScoringRule for FlatScoring {
+    fn name(&self) -> &'static str {
+        "Flat"
+    }
+
+    fn clear_points(&self, clear: &Clear) -> u32 {
+        clear.lines * (clear.level as u32 + 1) * 500
+    }
+
+    fn soft_drop_points(&self, rows: u32) -> u32 {
+        rows
+    }
+
+    fn hard_drop_points(&self, rows: u32) -> u32 {
+        rows * 2
+    }
+}
+
+// 40/100/300/1200 times the level, and a point a row for pushing down
+pub struct NesScoring;
+
+impl ScoringRule for NesScoring {
+    fn name(&self) -> &'static str {
+        "NES"
+    }
+
+    fn clear_points(&self, clear: &Clear) -> u32 {
+        let base = [0, 40, 100, 300, 1200][(clear.lines as usize).min(4)];
+        base * (clear.level as u32 + 1)
+    }
+
+    fn soft_drop_points(&self, rows: u32) -> u32 {
+        rows
+    }
+
+    fn hard_drop_points(&self, _rows: u32) -> u32 {
+        0 // there was no hard drop on the NES
+    }
+}
+
+// The modern guideline: T-spins, back-to-back bonuses and combos
+pub struct GuidelineScoring;
+
+// guideline points for a T-spin, before the level multiplier
+pub fn t_spin_points(spin: Spin, lines: u32) -> u32 {
+    match (spin, lines) {
+        (Spin::Mini, 0) => 100,
+        (Spin::Mini, 1) => 200,
+        (Spin::Mini, _) => 400,
+        (Spin::Full, 0) => 400,
+        (Spin::Full, 1) => 800,
+        (Spin::Full, 2) => 1200,
+        (Spin::Full, _) => 1600,
+        (Spin::None, _) => 0
+    }
+}
+
+impl ScoringRule for GuidelineScoring {
+    fn name(&self) -> &'static str {
+        "Guideline"
+    }
+
+    fn clear_points(&self, clear: &Clear) -> u32 {
+        let level = clear.level as u32 + 1;
+        let mut points = match clear.spin {
+            Spin::None => [0, 100, 300, 500, 800][(clear.lines as usize).min(4)],
+            spin => t_spin_points(spin, clear.lines)
+        };
+
+        // worth half as much again
+        if clear.back_to_back {
+            points = points * 3 / 2;
+        }
+
+        if clear.combo > 0 {
+            points += 50 * clear.combo as u32;
+        }
+
+        points * level
+    }
+
+    fn soft_drop_points(&self, rows: u32) -> u32 {
+        rows
+    }
+
+    fn hard_drop_points(&self, rows: u32) -> u32 {
+        rows * 2
+    }
+
+    fn perfect_clear_points(&self, clear: &Clear) -> u32 {
+        let points = match clear.lines {
+            0 => 0,
+            1 => 800,
+            2 => 1200,
+            3 => 1800,
+            _ if clear.back_to_back => 3200,
+            _ => 2000
+        };
+        points * (clear.level as u32 + 1)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScoringKind {
+    Flat,
+    Nes,
+    Guideline
+}
+
+impl ScoringKind {
+    pub fn rule(self) -> &'static dyn ScoringRule {
+        match self {
+            ScoringKind::Flat => &FlatScoring,
+            ScoringKind::Nes => &NesScoring,
+            ScoringKind::Guideline => &GuidelineScoring
+        }
+    }
+}
+
+impl FromStr for ScoringKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flat" => Ok(ScoringKind::Flat),
+            "nes" => Ok(ScoringKind::Nes),
+            "guideline" => Ok(ScoringKind::Guideline),
+            _ => Err(format!("unknown scoring '{}' (try flat, nes or guideline)", s))
+        }
+    }
+}
+
+#[test]
+fn test_scoring_rules() {
+    let tetris = Clear { lines: 4, spin: Spin::None, back_to_back: false, combo: 0, level: 2 };
+    assert_eq!(ScoringKind::Flat.rule().clear_points(&tetris), 6000);
+    assert_eq!(ScoringKind::Nes.rule().clear_points(&tetris), 3600);
+    assert_eq!(ScoringKind::Guideline.rule().clear_points(&tetris), 2400);
+
+    let b2b_tsd = Clear { lines: 2, spin: Spin::Full, back_to_back: true, combo: 2, level: 0 };
+    assert_eq!(ScoringKind::Guideline.rule().clear_points(&b2b_tsd), 1800 + 100);
+    assert_eq!(ScoringKind::Nes.rule().clear_points(&b2b_tsd), 100);
+
+    assert_eq!(ScoringKind::Nes.rule().hard_drop_points(10), 0);
+
+    let b2b_tetris = Clear { back_to_back: true, ..tetris };
+    assert_eq!(ScoringKind::Guideline.rule().perfect_clear_points(&tetris), 6000);
+    assert_eq!(ScoringKind::Guideline.rule().perfect_clear_points(&b2b_tetris), 9600);
+    assert_eq!(ScoringKind::Flat.rule().perfect_clear_points(&tetris), 0);
+}